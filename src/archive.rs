@@ -0,0 +1,113 @@
+mod rar;
+mod zip;
+
+pub use self::{
+    rar::{RarBackend, RarError},
+    zip::{ZipBackend, ZipError},
+};
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use failure::Fail;
+use mime::Mime;
+use tempfile::TempDir;
+
+use crate::util;
+
+/// A file inside of an archive
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Sanitized path relative to the archive root
+    pub name: PathBuf,
+    /// Uncompressed size as declared by the archive, not to be trusted
+    pub size: u64,
+}
+
+pub trait ArchiveBackend {
+    type Error: Fail + From<io::Error>;
+
+    /// Lists all files in the archive in archive order
+    fn list(&self, path: &Path) -> Result<Vec<Entry>, Self::Error>;
+
+    /// Extracts all files of the archive into `dest`, failing if more than `max_size` bytes would
+    /// be written
+    fn extract(&self, path: &Path, dest: &Path, max_size: u64) -> Result<(), Self::Error>;
+}
+
+/// Error for archives that contain more than they claim to
+pub fn size_mismatch() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Archive contents are bigger than declared",
+    )
+}
+
+/// Copies at most `max_size` bytes from `r` to `w`, failing if `r` has more than that
+pub fn copy_limited<R, W>(r: &mut R, w: &mut W, max_size: u64) -> io::Result<u64>
+where
+    R: Read,
+    W: Write,
+{
+    let copied = io::copy(&mut r.take(max_size.saturating_add(1)), w)?;
+    if copied > max_size {
+        Err(size_mismatch())
+    } else {
+        Ok(copied)
+    }
+}
+
+/// Extracts the previously listed `entries` of the archive into a fresh tempdir and returns the
+/// tempdir with the extracted files in archive order. Fails if more than `max_size` bytes would be
+/// written.
+pub fn tmp_extract<B, P>(
+    backend: &B,
+    path: P,
+    entries: Vec<Entry>,
+    max_size: u64,
+) -> Result<(TempDir, Vec<PathBuf>), B::Error>
+where
+    B: ArchiveBackend,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let ret = TempDir::new()?;
+    backend.extract(path, ret.path(), max_size)?;
+    let files = entries
+        .into_iter()
+        .map(|entry| ret.path().join(entry.name))
+        .collect();
+
+    Ok((ret, files))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Rar,
+}
+
+impl ArchiveKind {
+    pub fn from_mime(mime: &Mime) -> Option<Self> {
+        if *mime == *util::APPLICATION_ZIP {
+            Some(ArchiveKind::Zip)
+        } else if *mime == *util::APPLICATION_RAR || *mime == *util::APPLICATION_VND_RAR {
+            Some(ArchiveKind::Rar)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for ArchiveKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match *self {
+            ArchiveKind::Zip => "zip",
+            ArchiveKind::Rar => "rar",
+        };
+        write!(f, "{}", s)
+    }
+}
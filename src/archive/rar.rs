@@ -0,0 +1,292 @@
+use std::{
+    ffi::{CStr, CString},
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    os::raw::c_int,
+    path::{Component, Path, PathBuf},
+    ptr, slice,
+};
+
+use failure::Fail;
+use unrar::{
+    error::{Code, UnrarError, When},
+    Archive,
+};
+use unrar_sys as native;
+
+use super::{size_mismatch, ArchiveBackend, Entry};
+
+#[derive(Debug, Fail)]
+pub enum RarError {
+    #[fail(display = "{}", _0)]
+    Io(#[cause] io::Error),
+    #[fail(display = "{}", _0)]
+    Unrar(UnrarCode),
+    #[fail(display = "libunrar can't handle non utf-8 path {:?}", _0)]
+    NonUtf8Path(PathBuf),
+}
+
+/// The parts of `UnrarError` that are `Send + Sync`
+#[derive(Debug, Clone, Copy)]
+pub struct UnrarCode(Code, When);
+
+impl fmt::Display for UnrarCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", UnrarError::<()>::from(self.0, self.1))
+    }
+}
+
+impl From<io::Error> for RarError {
+    fn from(e: io::Error) -> Self {
+        RarError::Io(e)
+    }
+}
+
+impl<T> From<UnrarError<T>> for RarError {
+    fn from(e: UnrarError<T>) -> Self {
+        RarError::Unrar(UnrarCode(e.code, e.when))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RarBackend;
+
+fn path_str(path: &Path) -> Result<String, RarError> {
+    path.to_str()
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| RarError::NonUtf8Path(path.to_owned()))
+}
+
+/// Strips everything from an entry name that could escape the extraction directory, like
+/// `ZipFile::sanitized_name` does
+fn sanitize(name: &str) -> PathBuf {
+    Path::new(name)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(c) => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+fn unrar_error(code: c_int, when: When) -> RarError {
+    let code = Code::from(code as u32).unwrap_or(Code::Unknown);
+    RarError::Unrar(UnrarCode(code, when))
+}
+
+/// Where libunrar's data callback puts the entry that is being read
+struct Sink<'a> {
+    out: Option<&'a mut dyn Write>,
+    /// How many more bytes may be written
+    left: u64,
+    written: u64,
+    error: Option<io::Error>,
+}
+
+impl<'a> Sink<'a> {
+    fn new(out: Option<&'a mut dyn Write>, max_size: u64) -> Self {
+        Self {
+            out,
+            left: max_size,
+            written: 0,
+            error: None,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let len = data.len() as u64;
+        if len > self.left {
+            return Err(size_mismatch());
+        }
+        if let Some(ref mut out) = self.out {
+            out.write_all(data)?;
+        }
+        self.left -= len;
+        self.written += len;
+        Ok(())
+    }
+}
+
+extern "C" fn callback(
+    msg: native::UINT,
+    user_data: native::LPARAM,
+    p1: native::LPARAM,
+    p2: native::LPARAM,
+) -> c_int {
+    // `RarReader::call` points `user_data` at a sink that lives until libunrar returns
+    let sink = unsafe { &mut *(user_data as *mut Sink<'_>) };
+    match msg {
+        native::UCM_PROCESSDATA => {
+            let data = unsafe { slice::from_raw_parts(p1 as *const u8, p2 as usize) };
+            match sink.write(data) {
+                Ok(()) => 1,
+                Err(e) => {
+                    sink.error = Some(e);
+                    -1
+                }
+            }
+        }
+        // continue if the next volume exists, give up instead of asking for it
+        native::UCM_CHANGEVOLUME | native::UCM_CHANGEVOLUMEW => {
+            if p2 == native::RAR_VOL_NOTIFY {
+                1
+            } else {
+                -1
+            }
+        }
+        _ => 0,
+    }
+}
+
+/// A file header of the archive
+struct Header {
+    name: PathBuf,
+    /// Declared uncompressed size, libunrar doesn't unpack more than this
+    size: u64,
+    is_dir: bool,
+}
+
+/// Goes through a RAR archive entry by entry. Entries are handed to us by libunrar instead of it
+/// writing them to disk, so we choose which ones get read and how much of them.
+struct RarReader {
+    handle: native::Handle,
+}
+
+impl RarReader {
+    fn open(path: &Path) -> Result<Self, RarError> {
+        let name = CString::new(path_str(path)?)
+            .map_err(|e| RarError::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+        let mut data = native::OpenArchiveData::new(name.as_ptr(), native::RAR_OM_EXTRACT);
+        let handle = unsafe { native::RAROpenArchive(&mut data) };
+        if handle.is_null() {
+            return Err(unrar_error(data.open_result as c_int, When::Open));
+        }
+        // closes the archive again if opening it failed halfway
+        let ret = Self { handle };
+        if data.open_result != 0 {
+            return Err(unrar_error(data.open_result as c_int, When::Open));
+        }
+        Ok(ret)
+    }
+
+    /// Calls into libunrar with `sink` receiving the callbacks
+    fn call<F>(&mut self, sink: &mut Sink<'_>, f: F) -> c_int
+    where
+        F: FnOnce(native::Handle) -> c_int,
+    {
+        unsafe {
+            native::RARSetCallback(
+                self.handle,
+                callback,
+                sink as *mut Sink<'_> as native::LPARAM,
+            );
+        }
+        f(self.handle)
+    }
+
+    /// The header of the next entry, which then has to be either skipped or read
+    fn next_header(&mut self) -> Result<Option<Header>, RarError> {
+        let mut header = native::HeaderData::default();
+        let code = self.call(&mut Sink::new(None, 0), |handle| unsafe {
+            native::RARReadHeader(handle, &mut header)
+        });
+        match code {
+            native::ERAR_SUCCESS => {}
+            native::ERAR_END_ARCHIVE => return Ok(None),
+            code => return Err(unrar_error(code, When::Read)),
+        }
+
+        let name = unsafe { CStr::from_ptr(header.filename.as_ptr()) };
+        Ok(Some(Header {
+            name: sanitize(&name.to_string_lossy()),
+            size: u64::from(header.unp_size),
+            is_dir: header.flags & native::RHDF_DIRECTORY != 0,
+        }))
+    }
+
+    fn process(&mut self, sink: &mut Sink<'_>, operation: c_int) -> Result<(), RarError> {
+        let code = self.call(sink, |handle| unsafe {
+            native::RARProcessFile(handle, operation, ptr::null(), ptr::null())
+        });
+        if let Some(e) = sink.error.take() {
+            return Err(e.into());
+        }
+        match code {
+            native::ERAR_SUCCESS => Ok(()),
+            code => Err(unrar_error(code, When::Process)),
+        }
+    }
+
+    fn skip(&mut self) -> Result<(), RarError> {
+        self.process(&mut Sink::new(None, 0), native::RAR_SKIP)
+    }
+
+    /// Unpacks the current entry into `out`, failing if it has more than `max_size` bytes
+    fn read(&mut self, out: &mut dyn Write, max_size: u64) -> Result<u64, RarError> {
+        let mut sink = Sink::new(Some(out), max_size);
+        // testing unpacks without writing anything, the data only goes to the callback
+        self.process(&mut sink, native::RAR_TEST)?;
+        Ok(sink.written)
+    }
+}
+
+impl Drop for RarReader {
+    fn drop(&mut self) {
+        unsafe {
+            native::RARCloseArchive(self.handle);
+        }
+    }
+}
+
+impl ArchiveBackend for RarBackend {
+    type Error = RarError;
+
+    fn list(&self, path: &Path) -> Result<Vec<Entry>, RarError> {
+        let mut ret = Vec::new();
+        for entry in Archive::new(path_str(path)?).list()? {
+            let entry = entry?;
+            if entry.is_file() {
+                ret.push(Entry {
+                    name: sanitize(&entry.filename),
+                    size: u64::from(entry.unpacked_size),
+                });
+            }
+        }
+
+        Ok(ret)
+    }
+
+    // the entries come through the data callback, so nothing gets written past `max_size` even if
+    // the headers lie
+    fn extract(&self, path: &Path, dest: &Path, max_size: u64) -> Result<(), RarError> {
+        let mut rar = RarReader::open(path)?;
+        let mut written = 0;
+        while let Some(header) = rar.next_header()? {
+            let out_path = dest.join(&header.name);
+            if header.is_dir {
+                fs::create_dir_all(&out_path)?;
+                rar.skip()?;
+                continue;
+            }
+            if header.size > max_size - written {
+                return Err(size_mismatch().into());
+            }
+
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut out_fh = File::create(&out_path)?;
+            written += rar.read(&mut out_fh, max_size - written)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn rar_sanitize() {
+    assert_eq!(sanitize("../../etc/passwd"), PathBuf::from("etc/passwd"));
+    assert_eq!(sanitize("/abs/01.png"), PathBuf::from("abs/01.png"));
+    assert_eq!(sanitize("ch1/./01.png"), PathBuf::from("ch1/01.png"));
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::Path,
+};
+
+use failure::Fail;
+use zip::{self, read::ZipFile, ZipArchive};
+
+use super::{copy_limited, ArchiveBackend, Entry};
+
+#[derive(Debug, Fail)]
+pub enum ZipError {
+    #[fail(display = "{}", _0)]
+    Io(#[cause] io::Error),
+    #[fail(display = "{}", _0)]
+    Zip(#[cause] zip::result::ZipError),
+    #[fail(display = "Archive is encrypted and needs a password")]
+    PasswordRequired,
+    #[fail(display = "Wrong password")]
+    WrongPassword,
+}
+
+impl ZipError {
+    /// Whether asking for another password could fix this
+    pub fn is_password_error(&self) -> bool {
+        matches!(*self, ZipError::PasswordRequired | ZipError::WrongPassword)
+    }
+}
+
+impl From<io::Error> for ZipError {
+    fn from(e: io::Error) -> Self {
+        ZipError::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for ZipError {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::UnsupportedArchive(msg)
+                if msg == zip::result::ZipError::PASSWORD_REQUIRED =>
+            {
+                ZipError::PasswordRequired
+            }
+            e => ZipError::Zip(e),
+        }
+    }
+}
+
+/// Handles both ZipCrypto and AES encrypted archives if it's got a password
+#[derive(Debug, Clone, Default)]
+pub struct ZipBackend {
+    password: Option<String>,
+}
+
+impl ZipBackend {
+    pub fn with_password(password: Option<&str>) -> Self {
+        Self {
+            password: password.map(ToOwned::to_owned),
+        }
+    }
+
+    fn by_index<'a>(
+        &self,
+        zip: &'a mut ZipArchive<BufReader<File>>,
+        i: usize,
+    ) -> Result<ZipFile<'a>, ZipError> {
+        match self.password {
+            Some(ref password) => zip
+                .by_index_decrypt(i, password.as_bytes())?
+                .map_err(|_| ZipError::WrongPassword),
+            None => Ok(zip.by_index(i)?),
+        }
+    }
+}
+
+fn open(path: &Path) -> Result<ZipArchive<BufReader<File>>, ZipError> {
+    let fh = BufReader::new(File::open(path)?);
+    Ok(ZipArchive::new(fh)?)
+}
+
+impl ArchiveBackend for ZipBackend {
+    type Error = ZipError;
+
+    fn list(&self, path: &Path) -> Result<Vec<Entry>, ZipError> {
+        let mut zip = open(path)?;
+        let mut ret = Vec::with_capacity(zip.len());
+        // raw access doesn't need to decrypt anything
+        for i in 0..zip.len() {
+            let elem = zip.by_index_raw(i)?;
+            if !elem.is_dir() {
+                ret.push(Entry {
+                    name: elem.mangled_name(),
+                    size: elem.size(),
+                });
+            }
+        }
+
+        Ok(ret)
+    }
+
+    fn extract(&self, path: &Path, dest: &Path, max_size: u64) -> Result<(), ZipError> {
+        let mut zip = open(path)?;
+        let mut written = 0;
+
+        for i in 0..zip.len() {
+            let mut elem = self.by_index(&mut zip, i)?;
+            let out_path = dest.join(elem.mangled_name());
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            if elem.is_dir() {
+                fs::create_dir_all(&out_path)?;
+            } else {
+                let mut out_fh = File::create(&out_path)?;
+                written += copy_limited(&mut elem, &mut out_fh, max_size - written)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn zip_backend_roundtrip() {
+    use std::{io::Write, path::PathBuf};
+    use zip::write::{FileOptions, ZipWriter};
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let archive = dir.path().join("test.zip");
+    {
+        let mut w = ZipWriter::new(File::create(&archive).unwrap());
+        w.add_directory("sub/", FileOptions::default()).unwrap();
+        w.start_file("sub/b.png", FileOptions::default()).unwrap();
+        w.write_all(b"bbb").unwrap();
+        w.start_file("a.png", FileOptions::default()).unwrap();
+        w.write_all(b"a").unwrap();
+        w.finish().unwrap();
+    }
+
+    let entries = ZipBackend::default().list(&archive).unwrap();
+    let names: Vec<_> = entries.iter().map(|e| e.name.clone()).collect();
+    assert_eq!(entries[0].size, 3);
+    assert_eq!(
+        names,
+        vec![PathBuf::from("sub/b.png"), PathBuf::from("a.png")]
+    );
+
+    let tmp = tempfile::TempDir::new().unwrap();
+    assert!(ZipBackend::default()
+        .extract(&archive, tmp.path(), 3)
+        .is_err());
+
+    let (tmp, files) = super::tmp_extract(&ZipBackend::default(), &archive, entries, 4).unwrap();
+    assert_eq!(
+        files,
+        vec![tmp.path().join("sub/b.png"), tmp.path().join("a.png")]
+    );
+    assert_eq!(fs::read(&files[0]).unwrap(), b"bbb");
+    assert_eq!(fs::read(&files[1]).unwrap(), b"a");
+}
@@ -8,14 +8,35 @@ use crate::{
     percent_formatter::{PercentFormatBuf, PercentFormatable},
 };
 
+/// Name of a file, split into the archive it was extracted from and its path in that archive
+#[derive(Debug, Clone, Copy)]
+pub struct EntryName<'a> {
+    pub archive: Option<&'a str>,
+    pub entry: &'a str,
+}
+
+impl<'a> fmt::Display for EntryName<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.archive {
+            Some(archive) => write!(f, "{} › {}", archive, self.entry),
+            None => write!(f, "{}", self.entry),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct ImageInfo {
     filename: String,
+    archive: String,
+    entry: String,
     image_index: usize,
     nimages: usize,
     dims: (i32, i32),
     file_size: String,
     zoom: Percent,
+    marked: bool,
+    /// EXIF Orientation tag of the file, before it was applied
+    orientation: Option<u16>,
 }
 
 impl<W> PercentFormatable<W> for ImageInfo
@@ -25,11 +46,20 @@ where
     fn try_parse(&self, rest: &str, w: &mut W) -> Result<Option<usize>, fmt::Error> {
         match rest.chars().next() {
             Some('f') => write!(w, "{}", self.filename)?,
+            Some('a') => write!(w, "{}", self.archive)?,
+            Some('e') => write!(w, "{}", self.entry)?,
             Some('d') => write!(w, "{}x{}", self.dims.0, self.dims.1)?,
             Some('i') => write!(w, "{}", self.image_index)?,
             Some('n') => write!(w, "{}", self.nimages)?,
             Some('s') => write!(w, "{}", self.file_size)?,
             Some('z') => write!(w, "{}", self.zoom)?,
+            Some('m') if self.marked => write!(w, "*")?,
+            Some('m') => {}
+            Some('o') => {
+                if let Some(orientation) = self.orientation {
+                    write!(w, "{}", orientation)?
+                }
+            }
             _ => return Ok(None),
         }
 
@@ -42,6 +72,10 @@ pub struct BottomBar {
     label: gtk::Label,
     info: Option<ImageInfo>,
     formatter: PercentFormatBuf,
+    /// Whether files are still being searched for in the background
+    scanning: bool,
+    /// Transformation of the current image that wasn't saved yet
+    unsaved: Option<String>,
 }
 
 impl BottomBar {
@@ -56,6 +90,8 @@ impl BottomBar {
             label,
             info: None,
             formatter: PercentFormatBuf::new(fmt),
+            scanning: false,
+            unsaved: None,
         }
     }
 
@@ -65,7 +101,7 @@ impl BottomBar {
 
     pub fn set_info(
         &mut self,
-        filename: &str,
+        name: EntryName<'_>,
         dims: (i32, i32),
         file_size: u64,
         zoom: Percent,
@@ -75,7 +111,11 @@ impl BottomBar {
         let actual_index = image_index + 1;
         if let Some(ref mut info) = self.info {
             info.filename.clear();
-            info.filename.push_str(filename);
+            write!(info.filename, "{}", name).unwrap();
+            info.archive.clear();
+            info.archive.push_str(name.archive.unwrap_or(""));
+            info.entry.clear();
+            info.entry.push_str(name.entry);
             info.dims = dims;
             info.file_size.clear();
             write!(info.file_size, "{}", HumaneBytes::from(file_size)).unwrap();
@@ -84,12 +124,16 @@ impl BottomBar {
             info.nimages = nimages;
         } else {
             self.info = Some(ImageInfo {
-                filename: filename.to_owned(),
+                filename: name.to_string(),
+                archive: name.archive.unwrap_or("").to_owned(),
+                entry: name.entry.to_owned(),
                 dims,
                 file_size: format!("{}", HumaneBytes::from(file_size)),
                 zoom,
                 image_index: actual_index,
                 nimages,
+                marked: false,
+                orientation: None,
             });
         }
         self.render();
@@ -97,8 +141,55 @@ impl BottomBar {
 
     #[inline]
     fn render(&mut self) {
+        let mut parts = Vec::new();
         if let Some(ref info) = self.info {
-            self.label.set_text(self.formatter.format(info));
+            parts.push(self.formatter.format(info).to_owned());
+        }
+        if let Some(ref unsaved) = self.unsaved {
+            parts.push(format!("{}, unsaved", unsaved));
+        }
+        if self.scanning {
+            parts.push("scanning…".to_owned());
+        }
+        if !parts.is_empty() {
+            self.label.set_text(&parts.join(" | "));
+        }
+    }
+
+    /// Shows `msg` instead of the formatted info until the next `set_info`
+    pub fn set_message(&mut self, msg: &str) {
+        self.label.set_text(msg);
+    }
+
+    pub fn set_scanning(&mut self, scanning: bool) {
+        self.scanning = scanning;
+        self.render();
+    }
+
+    pub fn set_unsaved(&mut self, unsaved: Option<String>) {
+        self.unsaved = unsaved;
+        self.render();
+    }
+
+    pub fn set_index(&mut self, image_index: usize, nimages: usize) {
+        if let Some(ref mut info) = self.info {
+            info.image_index = image_index + 1;
+            info.nimages = nimages;
+            self.render();
+        }
+    }
+
+    pub fn set_marked(&mut self, marked: bool) {
+        if let Some(ref mut info) = self.info {
+            info.marked = marked;
+            self.render();
+        }
+    }
+
+    pub fn set_orientation(&mut self, orientation: Option<u16>) {
+        if let Some(ref mut info) = self.info {
+            info.orientation = orientation;
+            self.render();
         }
     }
 
@@ -109,3 +200,17 @@ impl BottomBar {
         }
     }
 }
+
+#[test]
+fn entry_name_display() {
+    let name = EntryName {
+        archive: Some("outer.zip › comic.cbz"),
+        entry: "012.png",
+    };
+    assert_eq!(name.to_string(), "outer.zip › comic.cbz › 012.png");
+    let name = EntryName {
+        archive: None,
+        entry: "photo.jpg",
+    };
+    assert_eq!(name.to_string(), "photo.jpg");
+}
@@ -17,8 +17,12 @@ use toml;
 use crate::{
     humane_bytes::HumaneBytes,
     keys::{KeyAction, KeyMap, KeyPress},
+    orientation::JpegRotation,
     percent::Percent,
     ratio::Ratio,
+    save::ExportOpts,
+    sort::SortOpts,
+    triage::Triage,
 };
 
 #[derive(Deserialize, Serialize)]
@@ -48,10 +52,80 @@ fn def_geom() -> WinGeom {
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct MaxFileSize {
-    pub zip: HumaneBytes,
+    #[serde(alias = "zip")]
+    pub archive: HumaneBytes,
     pub img: HumaneBytes,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct ArchiveLimits {
+    pub max_depth: usize,
+    pub max_total_size: HumaneBytes,
+    pub max_uncompressed_size: HumaneBytes,
+    pub max_entries: usize,
+    pub max_compression_ratio: f64,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        ArchiveLimits {
+            max_depth: 3,
+            max_total_size: HumaneBytes::try_from("1GB").unwrap(),
+            max_uncompressed_size: HumaneBytes::try_from("512MB").unwrap(),
+            max_entries: 10_000,
+            max_compression_ratio: 100.,
+        }
+    }
+}
+
+fn def_archive_limits() -> ArchiveLimits {
+    ArchiveLimits::default()
+}
+
+fn def_sort() -> SortOpts {
+    SortOpts::default()
+}
+
+fn def_watch() -> bool {
+    true
+}
+
+fn def_auto_reload() -> bool {
+    false
+}
+
+fn def_settle_ms() -> u64 {
+    500
+}
+
+fn def_triage() -> Triage {
+    Triage::default()
+}
+
+fn def_auto_orient() -> bool {
+    true
+}
+
+fn def_jpeg_rotation() -> JpegRotation {
+    JpegRotation::Exif
+}
+
+fn def_crop_ratios() -> Vec<Ratio> {
+    ["1x1", "4x3", "3x2", "16x9"]
+        .iter()
+        .map(|ratio| Ratio::try_from(*ratio).unwrap())
+        .collect()
+}
+
+fn def_export() -> ExportOpts {
+    ExportOpts::default()
+}
+
+fn def_max_pixels() -> u64 {
+    250_000_000
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub bottom_format: String,
@@ -59,6 +133,35 @@ pub struct Config {
     #[serde(with = "InterpTypeDef")]
     pub scaling_algo: InterpType,
     pub max_file_size: MaxFileSize,
+    #[serde(default = "def_archive_limits")]
+    pub archive_limits: ArchiveLimits,
+    #[serde(default = "def_max_pixels")]
+    pub max_pixels: u64,
+    #[serde(default = "def_sort")]
+    pub sort: SortOpts,
+    /// Update the file list when files in the opened directories change
+    #[serde(default = "def_watch")]
+    pub watch: bool,
+    /// How long a changed file's size has to stay the same before it gets loaded
+    #[serde(default = "def_settle_ms")]
+    pub settle_ms: u64,
+    /// Reload the current image when it changes on disk
+    #[serde(default = "def_auto_reload")]
+    pub auto_reload: bool,
+    #[serde(default = "def_triage")]
+    pub triage: Triage,
+    /// Rotate and mirror images as their EXIF orientation says
+    #[serde(default = "def_auto_orient")]
+    pub auto_orient: bool,
+    #[serde(default = "def_export")]
+    pub export: ExportOpts,
+    /// Aspect ratios the crop selection can be fixed to
+    #[serde(default = "def_crop_ratios")]
+    pub crop_ratios: Vec<Ratio>,
+    /// How saving a rotated JPEG works, the other formats get reencoded. `lossless` needs
+    /// `jpegtran`.
+    #[serde(default = "def_jpeg_rotation")]
+    pub jpeg_rotation: JpegRotation,
     #[serde(default = "def_geom")]
     pub initial_geom: WinGeom,
     pub keymap: KeyMap,
@@ -120,11 +223,11 @@ macro_rules! keymap {
 impl Default for Config {
     fn default() -> Self {
         Self {
-            bottom_format: "%d | %f | %s | %z | %i/%n".to_owned(),
+            bottom_format: "%m%d | %f | %s | %z | %i/%n".to_owned(),
             scrollbars: false,
             max_file_size: MaxFileSize {
                 img: HumaneBytes::try_from("25MB").unwrap(),
-                zip: HumaneBytes::try_from("256MB").unwrap(),
+                archive: HumaneBytes::try_from("256MB").unwrap(),
             },
             keymap: keymap! {
                 "q" => Quit,
@@ -149,9 +252,47 @@ impl Default for Config {
                 "e" => JumpToEnd,
                 "r" => RotateClockwise,
                 "R" => RotateCounterClockwise,
-                "f" => RotateUpsideDown
+                "f" => RotateUpsideDown,
+                "bar" => FlipHorizontal,
+                "underscore" => FlipVertical,
+                "BackSpace" => ResetTransform,
+                "s" => CycleSort,
+                "S" => ToggleSortReverse,
+                "F" => ToggleFollow,
+                "T" => ToggleSticky,
+                "F5" => Reload,
+                "x" => ToggleMark,
+                "Delete" => Trash,
+                "u" => Undo,
+                "F2" => Rename,
+                "N" => RenameMarked,
+                "A" => SaveTransform,
+                "E" => SaveAs,
+                "c" => ToggleCrop,
+                "a" => CycleCropRatio,
+                "C" => SaveCrop,
+                "y" => CopyCrop,
+                "Left" => CropNudgeLeft,
+                "Right" => CropNudgeRight,
+                "Up" => CropNudgeUp,
+                "Down" => CropNudgeDown,
+                "Y" => CopyImage,
+                "P" => CopyPath,
+                "U" => CopyUri,
+                "v" => Paste
             },
             scaling_algo: InterpType::Bilinear,
+            archive_limits: def_archive_limits(),
+            max_pixels: def_max_pixels(),
+            sort: def_sort(),
+            watch: def_watch(),
+            settle_ms: def_settle_ms(),
+            auto_reload: def_auto_reload(),
+            triage: def_triage(),
+            auto_orient: def_auto_orient(),
+            jpeg_rotation: def_jpeg_rotation(),
+            crop_ratios: def_crop_ratios(),
+            export: def_export(),
             initial_geom: def_geom(),
         }
     }
@@ -161,6 +302,13 @@ lazy_static! {
     static ref CONFIG_PATH: PathBuf = BaseDirs::new().unwrap().config_dir().join("iv.toml");
 }
 
+/// The user's data directory, like `~/.local/share`
+pub fn data_dir() -> io::Result<PathBuf> {
+    BaseDirs::new()
+        .map(|dirs| dirs.data_dir().to_owned())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Can't find home directory"))
+}
+
 pub fn load() -> Result<Config, failure::Error> {
     match fs::read_to_string(CONFIG_PATH.as_path()) {
         Ok(cont) => Ok(toml::from_str(&cont)?),
@@ -191,3 +339,10 @@ fn default_config_deserializeable() {
     gtk::init().unwrap();
     assert!(toml::to_string_pretty(&Config::default()).is_ok());
 }
+
+#[test]
+fn partial_archive_limits() {
+    let limits: ArchiveLimits = toml::from_str("max_depth = 5").unwrap();
+    assert_eq!(limits.max_depth, 5);
+    assert_eq!(limits.max_entries, ArchiveLimits::default().max_entries);
+}
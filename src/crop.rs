@@ -0,0 +1,121 @@
+use std::fmt;
+
+use crate::ratio::Ratio;
+
+/// A rectangle in image pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+impl Rect {
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    /// The same rectangle moved by `dx` and `dy`, but no further than the edges of a
+    /// `width`x`height` image
+    pub fn nudged(self, dx: i32, dy: i32, (width, height): (i32, i32)) -> Rect {
+        Rect {
+            x: (self.x + dx).min(width - self.width).max(0),
+            y: (self.y + dy).min(height - self.height).max(0),
+            ..self
+        }
+    }
+}
+
+/// The selection made by dragging from `anchor` to `pointer` on a `width`x`height` image.
+/// `pointer` sticks to the edges of the image when it's closer than `snap` pixels, and the
+/// selection is shrunk to `ratio` if there is one.
+pub fn drag_rect(
+    anchor: (f64, f64),
+    pointer: (f64, f64),
+    (width, height): (i32, i32),
+    ratio: Option<Ratio>,
+    snap: f64,
+) -> Rect {
+    let (width, height) = (f64::from(width), f64::from(height));
+    let snapped = |pos: f64, max: f64| {
+        if pos < snap {
+            0.
+        } else if pos > max - snap {
+            max
+        } else {
+            pos
+        }
+    };
+    let anchor = (anchor.0.max(0.).min(width), anchor.1.max(0.).min(height));
+    let pointer = (snapped(pointer.0, width), snapped(pointer.1, height));
+
+    let (mut w, mut h) = ((pointer.0 - anchor.0).abs(), (pointer.1 - anchor.1).abs());
+    let (left, up) = (pointer.0 < anchor.0, pointer.1 < anchor.1);
+    if let Some(ratio) = ratio {
+        let ratio = ratio.as_f64();
+        if w > h * ratio {
+            w = h * ratio;
+        } else {
+            h = w / ratio;
+        }
+        // no room left in the direction of the drag, shrink while keeping the ratio
+        let room_w = if left { anchor.0 } else { width - anchor.0 };
+        let room_h = if up { anchor.1 } else { height - anchor.1 };
+        let shrink = (room_w / w).min(room_h / h).min(1.);
+        if shrink.is_finite() {
+            w *= shrink;
+            h *= shrink;
+        }
+    } else {
+        w = w.min(if left { anchor.0 } else { width - anchor.0 });
+        h = h.min(if up { anchor.1 } else { height - anchor.1 });
+    }
+
+    let x = if left { anchor.0 - w } else { anchor.0 };
+    let y = if up { anchor.1 - h } else { anchor.1 };
+    Rect {
+        x: x.round() as i32,
+        y: y.round() as i32,
+        width: w.round() as i32,
+        height: h.round() as i32,
+    }
+}
+
+#[test]
+fn crop_drag_rect() {
+    use std::convert::TryFrom;
+
+    let rect = |x, y, width, height| Rect {
+        x,
+        y,
+        width,
+        height,
+    };
+
+    let bounds = (100, 50);
+    let free = drag_rect((10., 10.), (30., 20.), bounds, None, 0.);
+    assert_eq!(free, rect(10, 10, 20, 10));
+    // dragging backwards and outside of the image
+    let back = drag_rect((10., 10.), (-5., 3.), bounds, None, 0.);
+    assert_eq!(back, rect(0, 3, 10, 7));
+    let snapped = drag_rect((10., 10.), (97., 47.), bounds, None, 4.);
+    assert_eq!(snapped, rect(10, 10, 90, 40));
+
+    let square = Ratio::try_from("1x1").ok();
+    let sq = drag_rect((10., 10.), (40., 20.), bounds, square, 0.);
+    assert_eq!(sq, rect(10, 10, 10, 10));
+    // the ratio is kept when running into the edge
+    let wide = Ratio::try_from("2x1").ok();
+    let edge = drag_rect((80., 10.), (200., 200.), bounds, wide, 0.);
+    assert_eq!(edge, rect(80, 10, 20, 10));
+
+    let nudged = free.nudged(100, -3, bounds);
+    assert_eq!(nudged, rect(80, 7, 20, 10));
+}
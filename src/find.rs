@@ -1,28 +1,171 @@
 use std::{
-    io,
+    fs::File,
+    io::{self, Read},
     path::{Path, PathBuf},
+    thread,
 };
 
-use walkdir::WalkDir;
+use rayon::prelude::*;
+use walkdir::{DirEntry, WalkDir};
 
-pub fn find_files_rec<P: AsRef<Path>>(root: P) -> impl Iterator<Item = PathBuf> {
-    let root = root.as_ref();
-    WalkDir::new(root)
+use crate::{archive::ArchiveKind, util::mime_type_buf};
+
+/// Enough for libmagic to recognize every image format
+const MAGIC_LEN: u64 = 8 * 1024;
+
+/// Background scans double this after every batch, so the first image shows up quickly but huge
+/// trees don't get resorted for every few files
+const FIRST_BATCH: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FindOpts {
+    /// `Some(1)` only lists the directory itself, `None` recurses all the way down
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    /// Include files and directories starting with a dot
+    pub hidden: bool,
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    // never skip what the user passed explicitly
+    entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.')
+}
+
+/// Whether `path` looks like an image or an archive the viewer can open, judging by its first bytes
+pub fn is_viewable(path: &Path) -> bool {
+    let mut buf = Vec::new();
+    let read = File::open(path).and_then(|fh| fh.take(MAGIC_LEN).read_to_end(&mut buf));
+    if read.is_err() {
+        return false;
+    }
+    match mime_type_buf(&buf) {
+        Ok(mime) => mime.type_() == mime::IMAGE || ArchiveKind::from_mime(&mime).is_some(),
+        Err(_) => false,
+    }
+}
+
+/// libmagic is slow enough that classifying big directories in parallel is worth it
+fn keep_viewable(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    paths
+        .into_par_iter()
+        .filter(|path| is_viewable(path))
+        .collect()
+}
+
+/// Calls `f` with every file in `root` and whether it is `root` itself until `f` returns false.
+/// Fails only if `root` can't be read, errors further down are skipped.
+fn walk<F>(root: &Path, opts: &FindOpts, mut f: F) -> Result<(), io::Error>
+where
+    F: FnMut(PathBuf, bool) -> bool,
+{
+    let mut walker = WalkDir::new(root).follow_links(opts.follow_symlinks);
+    if let Some(depth) = opts.max_depth {
+        walker = walker.max_depth(depth);
+    }
+
+    for entry in walker
         .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.file_type().is_file())
-        .map(|entry| PathBuf::from(entry.path()))
-}
-
-pub fn find_files<P: AsRef<Path>>(root: P) -> Result<impl Iterator<Item = PathBuf>, io::Error> {
-    let root = root.as_ref();
-    root.read_dir().map(|entries| {
-        entries
-            .filter_map(|entry| {
-                let entry = entry.ok()?;
-                Some((entry.file_type().ok()?, entry))
-            })
-            .filter(|(t, _)| t.is_file())
-            .map(|(_, entry)| entry.path())
-    })
+        .filter_entry(|entry| opts.hidden || !is_hidden(entry))
+    {
+        match entry {
+            Ok(entry) => {
+                if entry.file_type().is_file() {
+                    let explicit = entry.depth() == 0;
+                    if !f(entry.into_path(), explicit) {
+                        break;
+                    }
+                }
+            }
+            Err(e) => {
+                if e.depth() == 0 {
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the viewable files in `root`, or just `root` if it's a file. Fails only if `root` itself
+/// can't be read, errors further down are skipped.
+pub fn find_files<P: AsRef<Path>>(root: P, opts: &FindOpts) -> Result<Vec<PathBuf>, io::Error> {
+    let mut explicit = None;
+    let mut found = Vec::new();
+    walk(root.as_ref(), opts, |path, is_root| {
+        // don't second guess the user
+        if is_root {
+            explicit = Some(path);
+        } else {
+            found.push(path);
+        }
+        true
+    })?;
+
+    let mut ret = keep_viewable(found);
+    ret.extend(explicit);
+    Ok(ret)
+}
+
+pub enum ScanMsg {
+    Found(Vec<PathBuf>),
+    Done,
+}
+
+struct Batcher {
+    sender: glib::Sender<ScanMsg>,
+    max_len: usize,
+    found: Vec<PathBuf>,
+    explicit: Vec<PathBuf>,
+}
+
+impl Batcher {
+    /// Returns false if nobody is listening anymore
+    fn push(&mut self, path: PathBuf, is_root: bool) -> bool {
+        if is_root {
+            self.explicit.push(path);
+        } else {
+            self.found.push(path);
+        }
+        if self.found.len() + self.explicit.len() < self.max_len {
+            return true;
+        }
+        self.max_len *= 2;
+        self.flush()
+    }
+
+    fn flush(&mut self) -> bool {
+        let mut batch = keep_viewable(self.found.split_off(0));
+        batch.append(&mut self.explicit);
+        batch.is_empty() || self.sender.send(ScanMsg::Found(batch)).is_ok()
+    }
+}
+
+/// Like `find_files` for every root, but in a background thread that sends what it found in
+/// batches
+pub fn scan_in_background(roots: Vec<PathBuf>, opts: FindOpts, sender: glib::Sender<ScanMsg>) {
+    thread::spawn(move || {
+        let mut batcher = Batcher {
+            sender,
+            max_len: FIRST_BATCH,
+            found: Vec::new(),
+            explicit: Vec::new(),
+        };
+        let mut listening = true;
+        for root in roots {
+            let res = walk(&root, &opts, |path, is_root| {
+                listening = batcher.push(path, is_root);
+                listening
+            });
+            if let Err(e) = res {
+                eprintln!("Can't open directory {:?}: {}", root, e);
+            }
+            if !listening {
+                return;
+            }
+        }
+        if batcher.flush() {
+            let _ = batcher.sender.send(ScanMsg::Done);
+        }
+    });
 }
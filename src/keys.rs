@@ -31,6 +31,33 @@ pub enum KeyAction {
     RotateClockwise,
     RotateCounterClockwise,
     RotateUpsideDown,
+    FlipHorizontal,
+    FlipVertical,
+    ResetTransform,
+    CycleSort,
+    ToggleSortReverse,
+    ToggleFollow,
+    ToggleSticky,
+    Reload,
+    ToggleMark,
+    Trash,
+    Undo,
+    Rename,
+    RenameMarked,
+    SaveTransform,
+    SaveAs,
+    ToggleCrop,
+    CycleCropRatio,
+    SaveCrop,
+    CopyCrop,
+    CropNudgeLeft,
+    CropNudgeRight,
+    CropNudgeUp,
+    CropNudgeDown,
+    CopyImage,
+    CopyPath,
+    CopyUri,
+    Paste,
 }
 
 pub type KeyMap = HashMap<KeyPress, KeyAction>;
@@ -1,28 +1,50 @@
 #![feature(try_from)]
 
-use std::{path::PathBuf, process::exit};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::exit,
+    time::Duration,
+};
 
 use failure::format_err;
 use gtk::prelude::*;
-use rayon::prelude::*;
 use structopt::StructOpt;
 
+mod archive;
 mod bottom_bar;
 mod config;
-mod extract;
+mod crop;
 mod find;
 mod humane_bytes;
 mod keys;
+mod metadata;
+mod orientation;
 mod parse;
 #[cfg_attr(feature = "cargo-clippy", allow(cast_lossless))]
 mod percent;
 mod percent_formatter;
 mod ratio;
+mod rename;
+mod save;
 mod scrollable_image;
+mod sort;
+mod stdin;
+mod trash;
+mod triage;
 mod util;
 mod viewer;
+mod watch;
 
-use crate::viewer::Viewer;
+use crate::{
+    config::Config,
+    find::{FindOpts, ScanMsg},
+    sort::{SortKey, SortOpts},
+    viewer::{with_viewer, Viewer},
+    watch::WatchMsg,
+};
 
 fn run() -> Result<(), failure::Error> {
     let opt = Opt::from_args();
@@ -40,10 +62,111 @@ fn run() -> Result<(), failure::Error> {
             eprintln!("{}", nice_err);
             display_error_dialog(nice_err);
         }
-        Ok(config) => {
-            let (images, hide_status) = opt_to_viewer_params(opt)?;
-            let app = Viewer::new(images, !hide_status, config);
+        Ok(mut config) => {
+            let password = match opt.password_file {
+                Some(ref path) => Some(
+                    fs::read_to_string(path)
+                        .map_err(|e| format_err!("Can't read password file {:?}: {}", path, e))?
+                        .trim_end_matches(&['\r', '\n'][..])
+                        .to_owned(),
+                ),
+                None => None,
+            };
+            if let Some(key) = opt.sort {
+                config.sort.by = key;
+            }
+            config.sort.reverse ^= opt.reverse;
+            if let Some(ref out) = opt.convert {
+                config.export.strip_metadata |= opt.strip_metadata;
+                return convert(&opt.paths, out, &config);
+            }
+            let (read_stdin, null) = (opt.read_stdin, opt.null);
+            let follow = opt.follow;
+            let watch = config.watch || follow;
+            let settle_time = Duration::from_millis(config.settle_ms);
+            let stdin_path = Path::new("-");
+            let stdin_data = if opt.paths.iter().any(|path| path == stdin_path) {
+                if read_stdin {
+                    return Err(format_err!(
+                        "Can't read both paths and image data from stdin"
+                    ));
+                }
+                // one more byte than allowed so the loader can tell the image is too big
+                let mut data = Vec::new();
+                io::stdin()
+                    .take(u64::from(config.max_file_size.img) + 1)
+                    .read_to_end(&mut data)
+                    .map_err(|e| format_err!("Can't read image from stdin: {}", e))?;
+                Some(data)
+            } else {
+                None
+            };
+            let params = opt_to_viewer_params(opt, &config.sort)?;
+            let app = Viewer::new(
+                params.images,
+                params.start,
+                params.watch.clone(),
+                !params.hide_status,
+                config,
+                password,
+            );
+            if let Some(data) = stdin_data {
+                app.borrow_mut()
+                    .add_memory_file(stdin_path.to_owned(), "<stdin>".to_owned(), data);
+            }
             app.borrow_mut().show_all();
+            if follow {
+                app.borrow_mut().toggle_follow();
+            }
+
+            if !params.scan.is_empty() {
+                app.borrow_mut().set_scanning(true);
+                let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+                find::scan_in_background(params.scan, params.find_opts, sender);
+                let app = app.clone();
+                receiver.attach(None, move |msg| {
+                    match msg {
+                        ScanMsg::Found(paths) => {
+                            with_viewer(&app, move |viewer| viewer.insert_paths(paths))
+                        }
+                        ScanMsg::Done => with_viewer(&app, |viewer| viewer.set_scanning(false)),
+                    }
+                    Continue(true)
+                });
+            }
+
+            if watch && !params.watch.is_empty() {
+                let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+                match watch::watch_in_background(
+                    params.watch,
+                    params.find_opts.max_depth,
+                    params.find_opts.hidden,
+                    settle_time,
+                    sender,
+                ) {
+                    Ok(()) => {
+                        let app = app.clone();
+                        receiver.attach(None, move |msg| {
+                            with_viewer(&app, move |viewer| match msg {
+                                WatchMsg::Created(path) => viewer.file_created(path),
+                                WatchMsg::Removed(path) => viewer.file_removed(&path),
+                                WatchMsg::Modified(path) => viewer.file_modified(path),
+                            });
+                            Continue(true)
+                        });
+                    }
+                    Err(e) => eprintln!("Can't watch directories: {}", e),
+                }
+            }
+
+            if read_stdin {
+                let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+                stdin::read_paths(null, sender);
+                receiver.attach(None, move |paths| {
+                    with_viewer(&app, move |viewer| viewer.append_paths(paths));
+                    Continue(true)
+                });
+            }
         }
     }
 
@@ -68,36 +191,162 @@ fn display_error_dialog(nice_err: String) {
     });
 }
 
+struct ViewerParams {
+    images: Vec<PathBuf>,
+    /// Index into `images` to start at
+    start: usize,
+    hide_status: bool,
+    find_opts: FindOpts,
+    /// Directories to scan in the background
+    scan: Vec<PathBuf>,
+    /// Directories to watch for changes
+    watch: Vec<PathBuf>,
+}
+
 #[inline]
 fn opt_to_viewer_params(
     Opt {
         hide_status,
         recursive,
+        max_depth,
+        follow_symlinks,
+        hidden,
+        siblings,
+        read_stdin,
         paths,
         ..
     }: Opt,
-) -> Result<(Vec<PathBuf>, bool), failure::Error> {
+    sort: &SortOpts,
+) -> Result<ViewerParams, failure::Error> {
+    let find_opts = FindOpts {
+        max_depth: max_depth.or(if recursive { None } else { Some(1) }),
+        follow_symlinks,
+        hidden,
+    };
+
+    // with -i the paths from stdin are appended later
+    let paths = if paths.is_empty() && !read_stdin {
+        vec![PathBuf::from(".")]
+    } else {
+        paths
+    };
+
+    if siblings || (!recursive && !read_stdin && paths.len() == 1 && paths[0].is_file()) {
+        let (images, start, watch) = with_siblings(paths, sort, &find_opts)?;
+        return Ok(ViewerParams {
+            images,
+            start,
+            hide_status,
+            find_opts,
+            scan: Vec::new(),
+            watch,
+        });
+    }
+
+    // big trees take a while, show the first images while the rest is still being found
     if recursive {
-        let mut ret: Vec<PathBuf> = if paths.is_empty() {
-            find::find_files_rec(".").collect()
+        let (dirs, images): (Vec<PathBuf>, _) = paths.into_iter().partition(|path| path.is_dir());
+        return Ok(ViewerParams {
+            images,
+            start: 0,
+            hide_status,
+            find_opts,
+            scan: dirs.clone(),
+            watch: dirs,
+        });
+    }
+
+    // directories are sorted on their own so the order of the arguments is kept
+    let mut ret = Vec::new();
+    let mut watch = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut files = find::find_files(&path, &find_opts)
+                .map_err(|e| format_err!("Can't open directory {:?}: {}", path, e))?;
+            sort.sort(&mut files);
+            ret.extend(files);
+            watch.push(path);
         } else {
-            paths.into_iter().flat_map(find::find_files_rec).collect()
-        };
+            ret.push(path);
+        }
+    }
+    Ok(ViewerParams {
+        images: ret,
+        start: 0,
+        hide_status,
+        find_opts,
+        scan: Vec::new(),
+        watch,
+    })
+}
 
-        // recursive dirwalking can produce a huge amount of results so why not sort it
-        // in parallel
-        ret.par_sort_unstable();
-        Ok((ret, hide_status))
-    } else {
-        let paths = if paths.is_empty() {
-            find::find_files(".")
-                .map_err(|e| format_err!("Can't open current directory: {}", e))?
-                .collect()
+/// Replaces every file in `paths` with the sorted contents of its directory. Returns the files,
+/// the index of the first given file and the directories.
+fn with_siblings(
+    paths: Vec<PathBuf>,
+    sort: &SortOpts,
+    find_opts: &FindOpts,
+) -> Result<(Vec<PathBuf>, usize, Vec<PathBuf>), failure::Error> {
+    let mut ret = Vec::new();
+    let mut seen_dirs = HashSet::new();
+    let mut dirs = Vec::new();
+    let mut start = None;
+    for path in paths {
+        let (dir, name) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) if path.is_file() => (dir, name),
+            _ => {
+                start = start.or(Some(ret.len()));
+                ret.push(path);
+                continue;
+            }
+        };
+        // `photo.jpg` has an empty parent
+        let dir = if dir == Path::new("") {
+            Path::new(".")
         } else {
-            paths
+            dir
         };
-        Ok((paths, hide_status))
+        let file = dir.join(name);
+        if !seen_dirs.insert(dir.to_owned()) {
+            start = start.or_else(|| ret.iter().position(|p| *p == file));
+            continue;
+        }
+        dirs.push(dir.to_owned());
+
+        let mut siblings = find::find_files(dir, find_opts)
+            .map_err(|e| format_err!("Can't open directory {:?}: {}", dir, e))?;
+        sort.sort(&mut siblings);
+        let pos = match siblings.iter().position(|p| *p == file) {
+            Some(pos) => pos,
+            None => {
+                siblings.insert(0, file);
+                0
+            }
+        };
+        start = start.or(Some(ret.len() + pos));
+        ret.extend(siblings);
     }
+
+    Ok((ret, start.unwrap_or(0), dirs))
+}
+
+/// Converts the single image in `paths` to `out`
+fn convert(paths: &[PathBuf], out: &Path, config: &Config) -> Result<(), failure::Error> {
+    let path = match paths {
+        [path] => path,
+        _ => return Err(format_err!("--convert needs exactly one image")),
+    };
+    let data = if path == Path::new("-") {
+        let mut data = Vec::new();
+        io::stdin()
+            .take(u64::from(config.max_file_size.img) + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| format_err!("Can't read image from stdin: {}", e))?;
+        data
+    } else {
+        fs::read(path).map_err(|e| format_err!("Can't read {:?}: {}", path, e))?
+    };
+    viewer::convert(path, &data, out, config)
 }
 
 #[derive(StructOpt)]
@@ -105,7 +354,7 @@ fn opt_to_viewer_params(
 /// It views images
 struct Opt {
     #[structopt(name = "PATHS", parse(from_os_str))]
-    /// The things you want to view
+    /// The things you want to view, `-` reads an image from stdin
     paths: Vec<PathBuf>,
     #[structopt(short = "s", long = "hide-status")]
     /// Hide bottom status bar
@@ -113,6 +362,45 @@ struct Opt {
     #[structopt(short = "r", long = "recursive")]
     /// Recurse into directories
     recursive: bool,
+    #[structopt(long = "max-depth")]
+    /// How deep to descend into directories, 1 only opens their files. Defaults to 1 without
+    /// --recursive
+    max_depth: Option<usize>,
+    #[structopt(long = "follow-symlinks")]
+    /// Follow symbolic links to files and directories
+    follow_symlinks: bool,
+    #[structopt(long = "hidden")]
+    /// Include hidden files and directories
+    hidden: bool,
+    #[structopt(short = "i", long = "stdin")]
+    /// Read paths separated by newlines from stdin, appended while iv is already open
+    read_stdin: bool,
+    #[structopt(short = "0", long = "null")]
+    /// Paths read with -i are separated by NUL bytes, like `find -print0` writes them
+    null: bool,
+    #[structopt(long = "siblings")]
+    /// Open the directories of the given files and start at the first file, the default when
+    /// only one file is given
+    siblings: bool,
+    #[structopt(long = "follow")]
+    /// Jump to new images as they appear in the opened directories, starting at the newest
+    follow: bool,
+    #[structopt(long = "sort")]
+    /// Sort by name, natural_name, mtime, size, dimensions, exif_date or shuffle
+    sort: Option<SortKey>,
+    #[structopt(long = "reverse")]
+    /// Reverse the sort order
+    reverse: bool,
+    #[structopt(long = "password-file", parse(from_os_str))]
+    /// Read the password for encrypted archives from this file
+    password_file: Option<PathBuf>,
+    #[structopt(long = "convert", parse(from_os_str))]
+    /// Write the given image to this file instead of showing it, in the format its extension
+    /// stands for
+    convert: Option<PathBuf>,
+    #[structopt(long = "strip-metadata")]
+    /// Leave out EXIF data when converting
+    strip_metadata: bool,
     #[structopt(long = "write-default")]
     /// Just write the default config, clobbering the old one
     write_default: bool,
@@ -0,0 +1,370 @@
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::{BufReader, Cursor},
+    ops::Range,
+    path::Path,
+};
+
+use exif::{Exif, In, Reader, Tag, Value};
+use gdk_pixbuf::Pixbuf;
+
+/// Reads the dimensions from the image header without decoding the whole image
+pub fn dimensions<P: AsRef<Path>>(path: P) -> Option<(i32, i32)> {
+    Pixbuf::get_file_info(path).map(|(_, width, height)| (width, height))
+}
+
+fn read_exif(path: &Path) -> Option<Exif> {
+    let mut fh = BufReader::new(File::open(path).ok()?);
+    Reader::new().read_from_container(&mut fh).ok()
+}
+
+/// The EXIF Orientation tag of an image file's contents
+pub fn orientation(data: &[u8]) -> Option<u16> {
+    let exif = Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()?;
+    let field = exif.get_field(Tag::Orientation, In::PRIMARY)?;
+    field.value.get_uint(0).map(|value| value as u16)
+}
+
+/// When the photo was taken as `YYYY:MM:DD HH:MM:SS`, which conveniently sorts chronologically
+pub fn exif_date<P: AsRef<Path>>(path: P) -> Option<String> {
+    let exif = read_exif(path.as_ref())?;
+    let field = exif
+        .get_field(Tag::DateTimeOriginal, In::PRIMARY)
+        .or_else(|| exif.get_field(Tag::DateTime, In::PRIMARY))?;
+    match field.value {
+        Value::Ascii(ref strs) => strs
+            .first()
+            .map(|s| String::from_utf8_lossy(s).into_owned()),
+        _ => None,
+    }
+}
+
+const ORIENTATION_TAG: u16 = 0x0112;
+const SHORT: u16 = 3;
+
+/// Byte order of a TIFF structure
+#[derive(Debug, Clone, Copy)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn u16(self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self {
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn u16_bytes(self, value: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::Little => value.to_le_bytes(),
+            ByteOrder::Big => value.to_be_bytes(),
+        }
+    }
+
+    fn u32_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::Little => value.to_le_bytes(),
+            ByteOrder::Big => value.to_be_bytes(),
+        }
+    }
+
+    fn write_u16(self, value: u16, to: &mut [u8]) {
+        to[..2].copy_from_slice(&self.u16_bytes(value));
+    }
+
+    /// The byte order a TIFF structure starts with
+    fn of(tiff: &[u8]) -> Option<ByteOrder> {
+        match tiff.get(..2)? {
+            b"II" => Some(ByteOrder::Little),
+            b"MM" => Some(ByteOrder::Big),
+            _ => None,
+        }
+    }
+}
+
+/// Where the segments of a JPEG start, up to the image data. Yields the marker and the range of
+/// the segment's payload.
+fn jpeg_segments(data: &[u8]) -> Vec<(u8, Range<usize>)> {
+    let mut ret = Vec::new();
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return ret;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xff {
+        let marker = data[pos + 1];
+        // start of scan, the rest is image data
+        if marker == 0xda {
+            break;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if len < 2 || pos + 2 + len > data.len() {
+            break;
+        }
+        ret.push((marker, pos + 4..pos + 2 + len));
+        pos += 2 + len;
+    }
+    ret
+}
+
+/// Range of the TIFF structure in the EXIF segment of a JPEG
+fn jpeg_exif(data: &[u8]) -> Option<Range<usize>> {
+    jpeg_segments(data)
+        .into_iter()
+        .find(|(marker, range)| *marker == 0xe1 && data[range.clone()].starts_with(b"Exif\0\0"))
+        .map(|(_, range)| range.start + 6..range.end)
+}
+
+/// Offset of the Orientation value in `tiff`
+fn find_orientation(tiff: &[u8]) -> Option<(usize, ByteOrder)> {
+    let order = ByteOrder::of(tiff)?;
+    let ifd = order.u32(tiff.get(4..8)?) as usize;
+    let count = order.u16(tiff.get(ifd..ifd + 2)?) as usize;
+    (0..count)
+        .map(|i| ifd + 2 + i * 12)
+        .take_while(|&entry| entry + 12 <= tiff.len())
+        .find(|&entry| {
+            order.u16(&tiff[entry..]) == ORIENTATION_TAG && order.u16(&tiff[entry + 2..]) == SHORT
+        })
+        .map(|entry| (entry + 8, order))
+}
+
+/// `tiff` with an Orientation entry added to IFD0. The extended IFD0 gets appended and the header
+/// points to it instead of the old one, so the offsets of everything else stay valid.
+fn add_orientation(tiff: &[u8], orientation: u16) -> Option<Vec<u8>> {
+    let order = ByteOrder::of(tiff)?;
+    let ifd = order.u32(tiff.get(4..8)?) as usize;
+    let count = order.u16(tiff.get(ifd..ifd + 2)?);
+    let entries_end = ifd + 2 + usize::from(count) * 12;
+    let entries = tiff.get(ifd + 2..entries_end)?;
+    let next_ifd = tiff.get(entries_end..entries_end + 4)?;
+
+    let mut entry = Vec::with_capacity(12);
+    entry.extend_from_slice(&order.u16_bytes(ORIENTATION_TAG));
+    entry.extend_from_slice(&order.u16_bytes(SHORT));
+    entry.extend_from_slice(&order.u32_bytes(1));
+    entry.extend_from_slice(&order.u16_bytes(orientation));
+    entry.extend_from_slice(&[0, 0]);
+    // entries are sorted by tag
+    let at = entries
+        .chunks(12)
+        .take_while(|entry| order.u16(entry) < ORIENTATION_TAG)
+        .count()
+        * 12;
+
+    let mut ret = tiff.to_vec();
+    // IFDs start on a word boundary
+    if ret.len() % 2 == 1 {
+        ret.push(0);
+    }
+    let new_ifd = u32::try_from(ret.len()).ok()?;
+    ret.extend_from_slice(&order.u16_bytes(count.checked_add(1)?));
+    ret.extend_from_slice(&entries[..at]);
+    ret.extend_from_slice(&entry);
+    ret.extend_from_slice(&entries[at..]);
+    ret.extend_from_slice(next_ifd);
+    ret[4..8].copy_from_slice(&order.u32_bytes(new_ifd));
+    Some(ret)
+}
+
+/// The EXIF Orientation value of a JPEG
+pub fn jpeg_orientation(data: &[u8]) -> Option<u16> {
+    let tiff = &data[jpeg_exif(data)?];
+    let (offset, order) = find_orientation(tiff)?;
+    Some(order.u16(&tiff[offset..]))
+}
+
+/// `data` with the EXIF Orientation of the JPEG set to `orientation`. The tag or a minimal EXIF
+/// segment is added if missing. `None` if the EXIF data is broken or `data` isn't a JPEG.
+pub fn set_jpeg_orientation(mut data: Vec<u8>, orientation: u16) -> Option<Vec<u8>> {
+    if let Some(range) = jpeg_exif(&data) {
+        if let Some((offset, order)) = find_orientation(&data[range.clone()]) {
+            order.write_u16(orientation, &mut data[range.start + offset..]);
+            return Some(data);
+        }
+        let tiff = add_orientation(&data[range.clone()], orientation)?;
+        // the segment length counts itself and the Exif header before the TIFF structure
+        let len = u16::try_from(2 + 6 + tiff.len()).ok()?;
+        let len_at = range.start - 6 - 2;
+        data[len_at..len_at + 2].copy_from_slice(&len.to_be_bytes());
+        data.splice(range, tiff);
+        return Some(data);
+    }
+
+    let mut tiff = b"MM\0\x2a\0\0\0\x08".to_vec();
+    // a single IFD entry and no next IFD
+    tiff.extend_from_slice(&[0, 1]);
+    tiff.extend_from_slice(&ORIENTATION_TAG.to_be_bytes());
+    tiff.extend_from_slice(&SHORT.to_be_bytes());
+    tiff.extend_from_slice(&[0, 0, 0, 1]);
+    tiff.extend_from_slice(&orientation.to_be_bytes());
+    tiff.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    if insert_jpeg_exif(&mut data, &tiff) {
+        Some(data)
+    } else {
+        None
+    }
+}
+
+/// Adds an EXIF segment containing `tiff` to a JPEG without one. Returns false if `data` isn't a
+/// JPEG or `tiff` doesn't fit into a segment.
+fn insert_jpeg_exif(data: &mut Vec<u8>, tiff: &[u8]) -> bool {
+    // after a JFIF header, which wants to be first
+    let at = match jpeg_segments(data).first() {
+        Some((0xe0, range)) => range.end,
+        Some(_) => 2,
+        None => return false,
+    };
+    let len = match u16::try_from(2 + 6 + tiff.len()) {
+        Ok(len) => len,
+        Err(_) => return false,
+    };
+    let mut segment = vec![0xff, 0xe1];
+    segment.extend_from_slice(&len.to_be_bytes());
+    segment.extend_from_slice(b"Exif\0\0");
+    segment.extend_from_slice(tiff);
+    data.splice(at..at, segment);
+    true
+}
+
+/// The raw EXIF data of an image file's contents, with the orientation reset because the pixels
+/// are going to be saved as they are shown
+pub fn exif_for_export(data: &[u8]) -> Option<Vec<u8>> {
+    let exif = Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()?;
+    let mut tiff = exif.buf().to_vec();
+    if let Some((offset, order)) = find_orientation(&tiff) {
+        order.write_u16(1, &mut tiff[offset..]);
+    }
+    Some(tiff)
+}
+
+/// Where the chunk after the IHDR chunk of a PNG starts
+fn png_after_ihdr(data: &[u8]) -> Option<usize> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") || data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let len = u32::from_be_bytes([data[8], data[9], data[10], data[11]]) as usize;
+    // length, type, data and CRC
+    let end = 8 + 4 + 4 + len + 4;
+    if end <= data.len() {
+        Some(end)
+    } else {
+        None
+    }
+}
+
+/// `data` of a freshly encoded `format` image with the raw EXIF data `tiff` added. Only JPEG
+/// and PNG can get EXIF data, other formats are returned unchanged.
+pub fn embed_exif(mut data: Vec<u8>, format: &str, tiff: &[u8]) -> Vec<u8> {
+    match format {
+        "jpeg" if jpeg_exif(&data).is_none() => {
+            insert_jpeg_exif(&mut data, tiff);
+            data
+        }
+        "png" => {
+            let (after_ihdr, len) = match (png_after_ihdr(&data), u32::try_from(tiff.len())) {
+                (Some(after_ihdr), Ok(len)) => (after_ihdr, len),
+                _ => return data,
+            };
+            let mut chunk = len.to_be_bytes().to_vec();
+            chunk.extend_from_slice(b"eXIf");
+            chunk.extend_from_slice(tiff);
+            let crc = crc32fast::hash(&chunk[4..]);
+            chunk.extend_from_slice(&crc.to_be_bytes());
+            data.splice(after_ihdr..after_ihdr, chunk);
+            data
+        }
+        _ => data,
+    }
+}
+
+#[test]
+fn jpeg_orientation_roundtrip() {
+    let jfif = [0xff, 0xe0, 0, 4, b'J', b'F'];
+    let mut jpeg = vec![0xff, 0xd8];
+    jpeg.extend_from_slice(&jfif);
+    jpeg.extend_from_slice(&[0xff, 0xda, 0, 2, 0xff, 0xd9]);
+    assert_eq!(jpeg_orientation(&jpeg), None);
+
+    let rotated = set_jpeg_orientation(jpeg.clone(), 6).unwrap();
+    assert_eq!(jpeg_orientation(&rotated), Some(6));
+    assert_eq!(&rotated[2..8], &jfif);
+    assert_eq!(rotated.len(), jpeg.len() + 36);
+    let back = set_jpeg_orientation(rotated, 1).unwrap();
+    assert_eq!(jpeg_orientation(&back), Some(1));
+    assert_eq!(back.len(), jpeg.len() + 36);
+}
+
+#[test]
+fn jpeg_orientation_added() {
+    for &order in &[ByteOrder::Little, ByteOrder::Big] {
+        // IFD0 with just a Make tag
+        let mut tiff = match order {
+            ByteOrder::Little => b"II\x2a\0".to_vec(),
+            ByteOrder::Big => b"MM\0\x2a".to_vec(),
+        };
+        tiff.extend_from_slice(&order.u32_bytes(8));
+        tiff.extend_from_slice(&order.u16_bytes(1));
+        tiff.extend_from_slice(&order.u16_bytes(0x010f));
+        tiff.extend_from_slice(&order.u16_bytes(2));
+        tiff.extend_from_slice(&order.u32_bytes(3));
+        tiff.extend_from_slice(b"iv\0\0");
+        tiff.extend_from_slice(&order.u32_bytes(0));
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0, 4, b'J', b'F'];
+        jpeg.extend_from_slice(&[0xff, 0xda, 0, 2, 0xff, 0xd9]);
+        assert!(insert_jpeg_exif(&mut jpeg, &tiff));
+        assert_eq!(jpeg_orientation(&jpeg), None);
+
+        let rotated = set_jpeg_orientation(jpeg, 8).unwrap();
+        assert_eq!(jpeg_orientation(&rotated), Some(8));
+        let exif = Reader::new()
+            .read_from_container(&mut Cursor::new(&rotated))
+            .unwrap();
+        let make = exif.get_field(Tag::Make, In::PRIMARY).unwrap();
+        assert_eq!(make.display_value().to_string(), "\"iv\"");
+        assert_eq!(orientation(&rotated), Some(8));
+    }
+}
+
+#[test]
+fn png_exif_after_ihdr() {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png.extend_from_slice(&13_u32.to_be_bytes());
+    png.extend_from_slice(b"IHDR");
+    png.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]);
+    png.extend_from_slice(&crc32fast::hash(&png[12..]).to_be_bytes());
+    let after_ihdr = png.len();
+    png.extend_from_slice(&[0, 0, 0, 0]);
+    png.extend_from_slice(b"IEND");
+    png.extend_from_slice(&0xae42_6082_u32.to_be_bytes());
+
+    let tiff = b"MM\0\x2a\0\0\0\x08\0\0\0\0\0\0";
+    let embedded = embed_exif(png.clone(), "png", tiff);
+    assert_eq!(&embedded[..after_ihdr], &png[..after_ihdr]);
+    let chunk = &embedded[after_ihdr..embedded.len() - 12];
+    assert_eq!(&chunk[..4], &(tiff.len() as u32).to_be_bytes());
+    assert_eq!(&chunk[4..8], b"eXIf");
+    assert_eq!(&chunk[8..8 + tiff.len()], tiff);
+    let crc = crc32fast::hash(&chunk[4..8 + tiff.len()]);
+    assert_eq!(&chunk[8 + tiff.len()..], &crc.to_be_bytes());
+    assert_eq!(&embedded[embedded.len() - 12..], &png[after_ihdr..]);
+
+    // not a PNG
+    assert_eq!(embed_exif(b"GIF89a".to_vec(), "png", tiff), b"GIF89a");
+}
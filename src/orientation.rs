@@ -0,0 +1,379 @@
+use std::{
+    fmt, fs, io,
+    path::Path,
+    process::{Command, Output},
+};
+
+use failure::{format_err, Fail};
+use gdk_pixbuf::{Pixbuf, PixbufRotation};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{crop::Rect, metadata, save};
+
+/// How a rotated JPEG gets saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JpegRotation {
+    /// Only change the EXIF Orientation tag, the pixels stay untouched. With `auto_orient` off the
+    /// tag would be ignored, so the pixels get rotated like with `Lossless`.
+    Exif,
+    /// Rotate the pixels without recompressing them. Needs `jpegtran` from libjpeg in the `PATH`,
+    /// without it or when the size isn't a multiple of the JPEG block size only the tag gets
+    /// changed.
+    Lossless,
+}
+
+/// A transformation like the ones the EXIF Orientation tag describes: an optional horizontal
+/// mirroring, followed by a rotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation {
+    mirror: bool,
+    /// Clockwise degrees, a multiple of 90
+    rotation: u32,
+}
+
+impl Orientation {
+    pub const NORMAL: Orientation = Orientation {
+        mirror: false,
+        rotation: 0,
+    };
+
+    pub const FLIP_HORIZONTAL: Orientation = Orientation {
+        mirror: true,
+        rotation: 0,
+    };
+
+    pub const FLIP_VERTICAL: Orientation = Orientation {
+        mirror: true,
+        rotation: 180,
+    };
+
+    pub fn rotated(clockwise: u32) -> Orientation {
+        Orientation {
+            mirror: false,
+            rotation: clockwise % 360,
+        }
+    }
+
+    pub fn from_exif(value: u16) -> Option<Orientation> {
+        let (mirror, rotation) = match value {
+            1 => (false, 0),
+            2 => (true, 0),
+            3 => (false, 180),
+            4 => (true, 180),
+            5 => (true, 270),
+            6 => (false, 90),
+            7 => (true, 90),
+            8 => (false, 270),
+            _ => return None,
+        };
+        Some(Orientation { mirror, rotation })
+    }
+
+    pub fn to_exif(self) -> u16 {
+        match (self.mirror, self.rotation) {
+            (false, 0) => 1,
+            (true, 0) => 2,
+            (false, 180) => 3,
+            (true, 180) => 4,
+            (true, 270) => 5,
+            (false, 90) => 6,
+            (true, 90) => 7,
+            (false, 270) => 8,
+            _ => unreachable!("rotation is always a multiple of 90"),
+        }
+    }
+
+    /// `self` followed by `other`
+    pub fn then(self, other: Orientation) -> Orientation {
+        // mirroring turns the earlier rotation around
+        let earlier = if other.mirror {
+            360 - self.rotation
+        } else {
+            self.rotation
+        };
+        Orientation {
+            mirror: self.mirror != other.mirror,
+            rotation: (earlier + other.rotation) % 360,
+        }
+    }
+
+    /// The transformation that undoes `self`
+    pub fn inverse(self) -> Orientation {
+        if self.mirror {
+            self
+        } else {
+            Orientation::rotated(360 - self.rotation)
+        }
+    }
+
+    pub fn is_normal(self) -> bool {
+        self == Orientation::NORMAL
+    }
+
+    /// Where `rect` on a `width`x`height` image ends up after this transformation
+    pub fn map_rect(self, rect: Rect, (width, height): (i32, i32)) -> Rect {
+        let rect = if self.mirror {
+            Rect {
+                x: width - rect.x - rect.width,
+                ..rect
+            }
+        } else {
+            rect
+        };
+        match self.rotation {
+            90 => Rect {
+                x: height - rect.y - rect.height,
+                y: rect.x,
+                width: rect.height,
+                height: rect.width,
+            },
+            180 => Rect {
+                x: width - rect.x - rect.width,
+                y: height - rect.y - rect.height,
+                ..rect
+            },
+            270 => Rect {
+                x: rect.y,
+                y: width - rect.x - rect.width,
+                width: rect.height,
+                height: rect.width,
+            },
+            _ => rect,
+        }
+    }
+
+    /// Whether width and height trade places
+    pub fn swaps_sides(self) -> bool {
+        self.rotation == 90 || self.rotation == 270
+    }
+
+    /// `pixbuf` with this transformation applied
+    pub fn apply(self, pixbuf: &Pixbuf) -> Option<Pixbuf> {
+        let mirrored = if self.mirror {
+            pixbuf.flip(true)?
+        } else {
+            pixbuf.clone()
+        };
+        match self.rotation {
+            90 => mirrored.rotate_simple(PixbufRotation::Clockwise),
+            180 => mirrored.rotate_simple(PixbufRotation::Upsidedown),
+            270 => mirrored.rotate_simple(PixbufRotation::Counterclockwise),
+            _ => Some(mirrored),
+        }
+    }
+
+    /// Arguments that make `jpegtran` apply this transformation
+    fn jpegtran_args(self) -> &'static [&'static str] {
+        match self.to_exif() {
+            2 => &["-flip", "horizontal"],
+            3 => &["-rotate", "180"],
+            4 => &["-flip", "vertical"],
+            5 => &["-transpose"],
+            6 => &["-rotate", "90"],
+            7 => &["-transverse"],
+            8 => &["-rotate", "270"],
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.mirror, self.rotation) {
+            (false, 0) => write!(f, "unchanged"),
+            (true, 0) => write!(f, "flipped horizontally"),
+            (true, 180) => write!(f, "flipped vertically"),
+            (false, rotation) => write!(f, "rotated {}°", rotation),
+            (true, rotation) => write!(f, "flipped horizontally, rotated {}°", rotation),
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(
+        display = "Can't set the orientation in the broken EXIF data of {:?}",
+        path
+    )]
+    BrokenExif { path: Box<Path> },
+    #[fail(display = "jpegtran failed: {}", msg)]
+    Jpegtran { msg: String },
+    #[fail(
+        display = "Can't rotate {:?} without jpegtran, auto_orient is off so the EXIF orientation would be ignored",
+        path
+    )]
+    NoJpegtran { path: Box<Path> },
+}
+
+/// The orientation `data` is displayed with
+fn displayed_orientation(data: &[u8], oriented: bool) -> Option<Orientation> {
+    if oriented {
+        metadata::jpeg_orientation(data).and_then(Orientation::from_exif)
+    } else {
+        None
+    }
+}
+
+fn save_jpeg_exif(
+    path: &Path,
+    transform: Orientation,
+    oriented: bool,
+) -> Result<(), failure::Error> {
+    let data = fs::read(path)?;
+    let current = displayed_orientation(&data, oriented).unwrap_or(Orientation::NORMAL);
+    let new = current.then(transform).to_exif();
+    let data = metadata::set_jpeg_orientation(data, new)
+        .ok_or_else(|| Error::BrokenExif { path: path.into() })?;
+    save::write_atomically(path, &data)?;
+    Ok(())
+}
+
+/// Runs `jpegtran` on `path`, `edges` is `-perfect` or `-trim` for what to do with the partial
+/// blocks at the right and bottom edges
+fn jpegtran(path: &Path, pixels: Orientation, edges: &str) -> io::Result<Output> {
+    Command::new("jpegtran")
+        .arg("-copy")
+        .arg("all")
+        .arg(edges)
+        .args(pixels.jpegtran_args())
+        .arg(path)
+        .output()
+}
+
+fn stderr_msg(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).trim().to_owned()
+}
+
+/// Returns a notice if the pixels couldn't be rotated exactly
+fn save_jpeg_lossless(
+    path: &Path,
+    transform: Orientation,
+    oriented: bool,
+) -> Result<Option<String>, failure::Error> {
+    let data = fs::read(path)?;
+    let current = displayed_orientation(&data, oriented);
+    // the pixels get rotated as they are displayed, so the tag has to go back to normal
+    let pixels = current.unwrap_or(Orientation::NORMAL).then(transform);
+    // the EXIF orientation only shows when it's applied, without that the edges get trimmed
+    let output = match jpegtran(path, pixels, "-perfect") {
+        Ok(output) if output.status.success() => output.stdout,
+        Ok(ref output) if oriented => {
+            save_jpeg_exif(path, transform, oriented)?;
+            return Ok(Some(format!(
+                "Only changed the EXIF orientation, {}",
+                stderr_msg(output)
+            )));
+        }
+        Ok(ref output) => {
+            let notice = format!("Trimmed the edges, {}", stderr_msg(output));
+            let output = jpegtran(path, pixels, "-trim")
+                .map_err(|e| Error::Jpegtran { msg: e.to_string() })?;
+            if !output.status.success() {
+                let msg = stderr_msg(&output);
+                return Err(Error::Jpegtran { msg }.into());
+            }
+            save_rotated(path, output.stdout, current.is_some())?;
+            return Ok(Some(notice));
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound && oriented => {
+            save_jpeg_exif(path, transform, oriented)?;
+            return Ok(Some(
+                "jpegtran not found, only changed the EXIF orientation".to_owned(),
+            ));
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(Error::NoJpegtran { path: path.into() }.into());
+        }
+        Err(e) => return Err(Error::Jpegtran { msg: e.to_string() }.into()),
+    };
+    save_rotated(path, output, current.is_some())?;
+    Ok(None)
+}
+
+/// Writes what `jpegtran` made of `path`, resetting the EXIF orientation if it `had_orientation`
+fn save_rotated(
+    path: &Path,
+    mut data: Vec<u8>,
+    had_orientation: bool,
+) -> Result<(), failure::Error> {
+    if had_orientation {
+        data = metadata::set_jpeg_orientation(data, Orientation::NORMAL.to_exif())
+            .ok_or_else(|| format_err!("jpegtran dropped the EXIF data of {:?}", path))?;
+    }
+    save::write_atomically(path, &data)?;
+    Ok(())
+}
+
+/// Saves `path` as displayed after `transform`. `oriented` is whether the file's own EXIF
+/// orientation was applied when displaying it. `pixbuf` is the transformed image, used for formats
+/// that get reencoded. Returns a notice if the file couldn't be saved the way `jpeg` asks for.
+pub fn save(
+    path: &Path,
+    transform: Orientation,
+    oriented: bool,
+    jpeg: JpegRotation,
+    pixbuf: &Pixbuf,
+) -> Result<Option<String>, failure::Error> {
+    let format = save::writable_format(path)?;
+    match (format.as_str(), jpeg) {
+        ("jpeg", JpegRotation::Exif) if oriented => {
+            save_jpeg_exif(path, transform, oriented).map(|()| None)
+        }
+        // an EXIF orientation would be ignored when the file is shown again
+        ("jpeg", JpegRotation::Exif) => {
+            save_jpeg_lossless(path, transform, oriented).map(|notice| {
+                Some(notice.unwrap_or_else(|| "auto_orient is off, rotated the pixels".to_owned()))
+            })
+        }
+        ("jpeg", JpegRotation::Lossless) => save_jpeg_lossless(path, transform, oriented),
+        _ => save::save_pixbuf(pixbuf, &format, path).map(|()| None),
+    }
+}
+
+#[test]
+fn orientation_compose() {
+    let cw = Orientation::rotated(90);
+    assert_eq!(cw.then(cw).to_exif(), 3);
+    assert_eq!(cw.then(Orientation::rotated(270)), Orientation::NORMAL);
+    for value in 1..=8 {
+        let orientation = Orientation::from_exif(value).unwrap();
+        assert_eq!(orientation.to_exif(), value);
+        assert_eq!(Orientation::NORMAL.then(orientation), orientation);
+    }
+    // transpose is its own inverse
+    let transpose = Orientation::from_exif(5).unwrap();
+    assert!(transpose.then(transpose).is_normal());
+    for value in 1..=8 {
+        let orientation = Orientation::from_exif(value).unwrap();
+        assert!(orientation.then(orientation.inverse()).is_normal());
+    }
+    let flips = Orientation::FLIP_HORIZONTAL.then(Orientation::FLIP_VERTICAL);
+    assert_eq!(flips, Orientation::rotated(180));
+    let rect = Rect {
+        x: 1,
+        y: 2,
+        width: 3,
+        height: 4,
+    };
+    let cw_rect = Rect {
+        x: 4,
+        y: 1,
+        width: 4,
+        height: 3,
+    };
+    assert_eq!(cw.map_rect(rect, (10, 10)), cw_rect);
+    for value in 1..=8 {
+        let orientation = Orientation::from_exif(value).unwrap();
+        let dims = if orientation.swaps_sides() {
+            (20, 10)
+        } else {
+            (10, 20)
+        };
+        let there = orientation.map_rect(rect, (10, 20));
+        assert_eq!(orientation.inverse().map_rect(there, dims), rect);
+    }
+    // rotating and then mirroring is a transpose
+    let mirror = Orientation::from_exif(2).unwrap();
+    assert_eq!(cw.then(mirror).to_exif(), 5);
+}
@@ -4,10 +4,11 @@ use std::fmt;
 enum State {
     GotPercent,
     Normal,
-    Skip(usize),
 }
 
 pub trait PercentFormatable<W: fmt::Write> {
+    /// `rest` starts after the `%`. Returns how many bytes after the first char of `rest` belong
+    /// to the specifier, or `None` if it isn't one.
     fn try_parse(&self, rest: &str, writer: &mut W) -> Result<Option<usize>, fmt::Error>;
 }
 
@@ -18,11 +19,16 @@ where
 {
     let mut state = State::Normal;
     let mut last = 0;
-    for (i, ch) in fmt.chars().enumerate() {
+    for (i, ch) in fmt.char_indices() {
+        // still inside of the last specifier
+        if i < last {
+            continue;
+        }
         state = match state {
             State::Normal => {
                 if ch == '%' {
                     write!(w, "{}", &fmt[last..i])?;
+                    last = i;
                     State::GotPercent
                 } else {
                     State::Normal
@@ -30,16 +36,16 @@ where
             }
             State::GotPercent => {
                 if ch == '%' {
+                    // the second one gets written with the next chunk
+                    last = i;
                     State::Normal
                 } else if let Some(skippie) = p.try_parse(&fmt[i..], &mut w)? {
-                    last = i + skippie + 1;
-                    State::Skip(skippie)
+                    last = i + ch.len_utf8() + skippie;
+                    State::Normal
                 } else {
                     State::Normal
                 }
             }
-            State::Skip(0) => State::Normal,
-            State::Skip(n) => State::Skip(n - 1),
         }
     }
 
@@ -73,3 +79,24 @@ impl PercentFormatBuf {
         &self.buf
     }
 }
+
+#[test]
+fn percent_format_specifiers() {
+    struct Dims(i32, i32);
+
+    impl<W: fmt::Write> PercentFormatable<W> for Dims {
+        fn try_parse(&self, rest: &str, w: &mut W) -> Result<Option<usize>, fmt::Error> {
+            match rest.chars().next() {
+                Some('w') => write!(w, "{}", self.0)?,
+                Some('h') => write!(w, "{}", self.1)?,
+                _ => return Ok(None),
+            }
+            Ok(Some(0))
+        }
+    }
+
+    let mut buf = PercentFormatBuf::new("%w%h");
+    assert_eq!(buf.format(&Dims(1, 2)), "12");
+    let mut buf = PercentFormatBuf::new("ä %w×%h › 100%% %x");
+    assert_eq!(buf.format(&Dims(640, 480)), "ä 640×480 › 100% %x");
+}
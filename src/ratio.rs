@@ -27,10 +27,15 @@ impl<'a> TryFrom<&'a str> for Ratio {
     }
 }
 
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.0, self.1)
+    }
+}
+
 impl Serialize for Ratio {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let out = format!("{}x{}", self.0, self.1);
-        serializer.serialize_str(&out)
+        serializer.serialize_str(&self.to_string())
     }
 }
 
@@ -66,6 +71,11 @@ impl Ratio {
         Some(Ratio(a.to_f64()?, b.to_f64()?))
     }
 
+    /// Width divided by height
+    pub fn as_f64(&self) -> f64 {
+        self.0 / self.1
+    }
+
     pub fn scale<T: FromPrimitive + ToPrimitive + Copy>(
         &self,
         a: T,
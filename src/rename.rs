@@ -0,0 +1,111 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::percent_formatter::{PercentFormatBuf, PercentFormatable};
+
+/// What a batch rename pattern gets filled with for a single file
+struct PatternArgs<'a> {
+    /// Starts at 1
+    counter: usize,
+    stem: &'a str,
+}
+
+impl<'a, W> PercentFormatable<W> for PatternArgs<'a>
+where
+    W: fmt::Write,
+{
+    fn try_parse(&self, rest: &str, w: &mut W) -> Result<Option<usize>, fmt::Error> {
+        // `%03i` is the counter padded to 3 digits
+        let width_len = rest
+            .find(|ch: char| !ch.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (width, spec) = rest.split_at(width_len);
+        match spec.chars().next() {
+            Some('i') => {
+                let pad = width.parse().unwrap_or(0);
+                if width.starts_with('0') {
+                    write!(w, "{:0pad$}", self.counter, pad = pad)?
+                } else {
+                    write!(w, "{:pad$}", self.counter, pad = pad)?
+                }
+            }
+            Some('f') if width.is_empty() => write!(w, "{}", self.stem)?,
+            _ => return Ok(None),
+        }
+
+        Ok(Some(width_len))
+    }
+}
+
+/// `path` with its file name replaced by `stem` and the old extension
+pub fn with_stem(path: &Path, stem: &str) -> PathBuf {
+    let mut name = OsString::from(stem);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// New paths for `paths` from `pattern`, where `%i` is a counter and `%f` the old stem. Fails if
+/// the names aren't unique or would overwrite something.
+pub fn batch_names(pattern: &str, paths: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut formatter = PercentFormatBuf::new(pattern);
+    let mut seen = HashSet::new();
+    let mut ret = Vec::with_capacity(paths.len());
+    for (i, path) in paths.iter().enumerate() {
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy());
+        let args = PatternArgs {
+            counter: i + 1,
+            stem: stem.as_ref().map_or("", |stem| stem),
+        };
+        let new_stem = formatter.format(&args);
+        if new_stem.is_empty() || new_stem.contains('/') {
+            return Err(format!("{:?} is not a valid file name", new_stem));
+        }
+
+        let new_path = with_stem(path, new_stem);
+        if !seen.insert(new_path.clone()) {
+            return Err(format!(
+                "Pattern gives {:?} more than once, use %i",
+                new_path
+            ));
+        }
+        ret.push(new_path);
+    }
+
+    // files are renamed one after the other, so even a target that gets renamed away later would
+    // be clobbered
+    if let Some((_, existing)) = paths
+        .iter()
+        .zip(&ret)
+        .find(|(old, new)| old != new && new.symlink_metadata().is_ok())
+    {
+        return Err(format!("Refusing to overwrite {:?}", existing));
+    }
+    Ok(ret)
+}
+
+#[test]
+fn rename_batch_pattern() {
+    let paths: Vec<PathBuf> = vec!["a/IMG_1.jpg", "a/IMG_2.JPG", "b/x.png"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    let expected: Vec<PathBuf> = vec!["a/trip_001.jpg", "a/trip_002.JPG", "b/trip_003.png"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(batch_names("trip_%03i", &paths), Ok(expected));
+    let expected: Vec<PathBuf> = vec!["a/IMG_1-1.jpg", "a/IMG_2-2.JPG", "b/x-3.png"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(batch_names("%f-%i", &paths), Ok(expected));
+    let same_ext = [PathBuf::from("a/1.png"), PathBuf::from("a/2.png")];
+    assert!(batch_names("same", &same_ext).is_err());
+}
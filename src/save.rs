@@ -0,0 +1,137 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use failure::Fail;
+use gdk_pixbuf::Pixbuf;
+use serde_derive::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::metadata;
+
+/// How images get exported with save as and `--convert`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOpts {
+    /// From 0 to 100, for JPEG and WebP
+    pub quality: u8,
+    /// Leave out the EXIF data of the original
+    pub strip_metadata: bool,
+}
+
+impl Default for ExportOpts {
+    fn default() -> Self {
+        ExportOpts {
+            quality: 90,
+            strip_metadata: false,
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Can't tell the format of {:?}", path)]
+    UnknownFormat { path: Box<Path> },
+    #[fail(display = "Can't save {} images", format)]
+    UnsupportedFormat { format: String },
+    #[fail(
+        display = "Don't know how to write {:?}, use an extension like .png",
+        path
+    )]
+    UnknownExtension { path: Box<Path> },
+}
+
+/// Name of the gdk-pixbuf format of `path`, if gdk-pixbuf can also write it
+pub fn writable_format(path: &Path) -> Result<String, Error> {
+    let (info, _, _) =
+        Pixbuf::get_file_info(path).ok_or_else(|| Error::UnknownFormat { path: path.into() })?;
+    let format = info
+        .get_name()
+        .ok_or_else(|| Error::UnknownFormat { path: path.into() })?
+        .as_str()
+        .to_owned();
+    if info.is_writable() {
+        Ok(format)
+    } else {
+        Err(Error::UnsupportedFormat { format })
+    }
+}
+
+/// Name of the writable gdk-pixbuf format that `path`'s extension stands for
+pub fn format_for_extension(path: &Path) -> Result<String, Error> {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .ok_or_else(|| Error::UnknownExtension { path: path.into() })?;
+    Pixbuf::get_formats()
+        .into_iter()
+        .filter(|format| format.is_writable())
+        .find(|format| {
+            format
+                .get_extensions()
+                .iter()
+                .any(|known| known.as_str() == ext)
+        })
+        .and_then(|format| format.get_name())
+        .map(|name| name.as_str().to_owned())
+        .ok_or_else(|| Error::UnknownExtension { path: path.into() })
+}
+
+/// Writes `data` to a temporary file next to `path` and moves it to `path`, so a failed write
+/// doesn't destroy what was there before. An existing file keeps its permissions.
+pub fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut tmp = NamedTempFile::new_in(dir)?;
+    tmp.write_all(data)?;
+    if let Ok(metadata) = path.metadata() {
+        tmp.as_file().set_permissions(metadata.permissions())?;
+    }
+    tmp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Saves `pixbuf` as a `format` image to `path`
+pub fn save_pixbuf(pixbuf: &Pixbuf, format: &str, path: &Path) -> Result<(), failure::Error> {
+    let data = pixbuf.save_to_bufferv(format, &[])?;
+    write_atomically(path, &data)?;
+    Ok(())
+}
+
+/// Saves `pixbuf` to `path` in the format its extension stands for. `original` is the file the
+/// image was loaded from, for copying its EXIF data.
+pub fn export(
+    pixbuf: &Pixbuf,
+    original: Option<&[u8]>,
+    path: &Path,
+    opts: ExportOpts,
+) -> Result<(), failure::Error> {
+    let format = format_for_extension(path)?;
+    export_as(pixbuf, original, &format, path, opts)
+}
+
+/// Like `export` but saves a `format` image whatever the extension of `path`
+pub fn export_as(
+    pixbuf: &Pixbuf,
+    original: Option<&[u8]>,
+    format: &str,
+    path: &Path,
+    opts: ExportOpts,
+) -> Result<(), failure::Error> {
+    let quality = opts.quality.min(100).to_string();
+    let options = match format {
+        "jpeg" | "webp" => vec![("quality", quality.as_str())],
+        _ => Vec::new(),
+    };
+    let mut data = pixbuf.save_to_bufferv(format, &options)?;
+    if !opts.strip_metadata {
+        if let Some(exif) = original.and_then(metadata::exif_for_export) {
+            data = metadata::embed_exif(data, format, &exif);
+        }
+    }
+    write_atomically(path, &data)?;
+    Ok(())
+}
@@ -1,16 +1,42 @@
+use std::{cell::Cell, rc::Rc};
+
 use gdk_pixbuf::{Pixbuf, PixbufAnimation};
 use gtk::{self, prelude::*};
 
 pub struct ScrollableImage {
     scroll_view: gtk::ScrolledWindow,
     image: gtk::Image,
+    /// On top of the image, for drawing a selection and getting mouse events
+    canvas: gtk::DrawingArea,
+    /// Scroll position to go to once the image got its new size
+    pending_scroll: Rc<Cell<Option<(f64, f64)>>>,
 }
 
 impl ScrollableImage {
     pub fn new(with_scrollbars: bool) -> ScrollableImage {
         let scroll_view = gtk::ScrolledWindow::new::<gtk::Adjustment, gtk::Adjustment>(None, None);
         let image = gtk::Image::new();
-        scroll_view.add(&image);
+        let canvas = gtk::DrawingArea::new();
+        canvas.add_events(
+            gdk::EventMask::BUTTON_PRESS_MASK
+                | gdk::EventMask::BUTTON_RELEASE_MASK
+                | gdk::EventMask::BUTTON1_MOTION_MASK,
+        );
+        let overlay = gtk::Overlay::new();
+        overlay.add(&image);
+        overlay.add_overlay(&canvas);
+        scroll_view.add(&overlay);
+        let pending_scroll = Rc::new(Cell::new(None));
+        {
+            // the adjustments already got the new size when the image gets allocated
+            let scroll_view = scroll_view.clone();
+            let pending_scroll = pending_scroll.clone();
+            overlay.connect_size_allocate(move |_, _| {
+                if let Some(scroll) = pending_scroll.take() {
+                    scroll_to(&scroll_view, scroll);
+                }
+            });
+        }
         if !with_scrollbars {
             if let Some(scroll) = scroll_view.get_hscrollbar() {
                 scroll.set_visible(false);
@@ -19,25 +45,73 @@ impl ScrollableImage {
                 scroll.set_visible(false);
             }
         }
-        ScrollableImage { scroll_view, image }
+        ScrollableImage {
+            scroll_view,
+            image,
+            canvas,
+            pending_scroll,
+        }
     }
 
     pub fn set_from_animation(&self, buf: &PixbufAnimation) {
+        self.pending_scroll.set(None);
         self.image.set_from_animation(buf)
     }
 
     pub fn set_from_pixbuf(&self, buf: &Pixbuf) {
+        self.pending_scroll.set(None);
         self.image.set_from_pixbuf(Some(buf))
     }
 
+    pub fn clear(&self) {
+        self.pending_scroll.set(None);
+        self.image.clear()
+    }
+
     pub fn as_widget(&self) -> &gtk::ScrolledWindow {
         &self.scroll_view
     }
 
+    pub fn canvas(&self) -> &gtk::DrawingArea {
+        &self.canvas
+    }
+
+    /// Where the top left corner of the shown image is on the canvas, and the shown image's size
+    pub fn image_geometry(&self) -> Option<((f64, f64), (i32, i32))> {
+        let pixbuf = self.image.get_pixbuf()?;
+        let (width, height) = (pixbuf.get_width(), pixbuf.get_height());
+        let alloc = self.canvas.get_allocation();
+        // gtk::Image centers what it shows
+        let offset = |space: i32, size: i32| f64::from((space - size).max(0) / 2);
+        Some((
+            (offset(alloc.width, width), offset(alloc.height, height)),
+            (width, height),
+        ))
+    }
+
     pub fn get_allocation(&self) -> gtk::Allocation {
         self.scroll_view.get_allocation()
     }
 
+    /// Horizontal and vertical scroll position
+    pub fn get_scroll(&self) -> (f64, f64) {
+        if let Some(scroll) = self.pending_scroll.get() {
+            return scroll;
+        }
+        let value = |adjust: Option<gtk::Adjustment>| adjust.map_or(0., |a| a.get_value());
+        (
+            value(self.scroll_view.get_hadjustment()),
+            value(self.scroll_view.get_vadjustment()),
+        )
+    }
+
+    /// Scrolls once the image is laid out at its current size, right after zooming the old size
+    /// would clamp the position
+    pub fn set_scroll(&self, scroll: (f64, f64)) {
+        self.pending_scroll.set(Some(scroll));
+        self.image.queue_resize();
+    }
+
     pub fn scroll(&self, scroll: ScrollT) {
         match scroll {
             ScrollT::Up | ScrollT::Down | ScrollT::StartV | ScrollT::EndV => {
@@ -74,6 +148,15 @@ impl ScrollableImage {
     }
 }
 
+fn scroll_to(scroll_view: &gtk::ScrolledWindow, (x, y): (f64, f64)) {
+    if let Some(hadjust) = scroll_view.get_hadjustment() {
+        hadjust.set_value(x);
+    }
+    if let Some(vadjust) = scroll_view.get_vadjustment() {
+        vadjust.set_value(y);
+    }
+}
+
 // gtk scrolltype is missing things
 #[derive(Debug, Copy, Clone)]
 pub enum ScrollT {
@@ -0,0 +1,544 @@
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::HashMap,
+    fmt, fs,
+    iter::Peekable,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::{Chars, FromStr},
+    time::SystemTime,
+};
+
+use rand::{seq::SliceRandom, thread_rng};
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::metadata;
+
+/// How file names are compared
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NameOrder {
+    /// Compare byte by byte, `page10` comes before `page2`
+    Plain,
+    /// Compare numbers by value, `page2` comes before `page10`
+    Natural,
+    /// Like `Natural` but `Page2` and `page2` are equal
+    NaturalIgnoreCase,
+}
+
+impl NameOrder {
+    pub fn cmp_str(self, a: &str, b: &str) -> Ordering {
+        match self {
+            NameOrder::Plain => a.cmp(b),
+            NameOrder::Natural => natural_cmp(a, b, false),
+            NameOrder::NaturalIgnoreCase => natural_cmp(a, b, true),
+        }
+    }
+
+    /// Compares paths component by component so files stay grouped by directory. Paths that only
+    /// differ in case are ordered byte by byte so the order is the same every time.
+    pub fn cmp_paths(self, a: &Path, b: &Path) -> Ordering {
+        if self == NameOrder::Plain {
+            return a.cmp(b);
+        }
+
+        let mut a_it = a.components();
+        let mut b_it = b.components();
+        loop {
+            match (a_it.next(), b_it.next()) {
+                (None, None) => return a.cmp(b),
+                (None, Some(_)) => return Ordering::Less,
+                (Some(_), None) => return Ordering::Greater,
+                (Some(x), Some(y)) => {
+                    let ord = self.cmp_str(
+                        &x.as_os_str().to_string_lossy(),
+                        &y.as_os_str().to_string_lossy(),
+                    );
+                    if ord != Ordering::Equal {
+                        return ord;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn sort(self, paths: &mut [PathBuf]) {
+        // recursive dirwalking can produce a huge amount of results so why not sort it
+        // in parallel
+        paths.par_sort_by(|a, b| self.cmp_paths(a, b));
+    }
+}
+
+/// What the image list is sorted by
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Name,
+    NaturalName,
+    Mtime,
+    Size,
+    /// Number of pixels
+    Dimensions,
+    /// When the photo was taken, files without EXIF data go last
+    ExifDate,
+    Shuffle,
+}
+
+const SORT_KEYS: [SortKey; 7] = [
+    SortKey::Name,
+    SortKey::NaturalName,
+    SortKey::Mtime,
+    SortKey::Size,
+    SortKey::Dimensions,
+    SortKey::ExifDate,
+    SortKey::Shuffle,
+];
+
+/// What a file gets sorted by, read from the file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortValue {
+    Time(SystemTime),
+    Number(u64),
+    Text(String),
+}
+
+/// Sort values by path, files that don't have one are left out
+pub type SortValues = HashMap<PathBuf, SortValue>;
+
+impl SortKey {
+    /// The key after this one, wraps around
+    pub fn next(self) -> Self {
+        let i = SORT_KEYS.iter().position(|&key| key == self).unwrap();
+        SORT_KEYS[(i + 1) % SORT_KEYS.len()]
+    }
+
+    /// Whether sorting by this reads every file, which takes a while for big lists
+    pub fn reads_files(self) -> bool {
+        matches!(
+            self,
+            SortKey::Mtime | SortKey::Size | SortKey::Dimensions | SortKey::ExifDate
+        )
+    }
+
+    fn value(self, path: &Path) -> Option<SortValue> {
+        match self {
+            SortKey::Mtime => fs::metadata(path)
+                .and_then(|m| m.modified())
+                .ok()
+                .map(SortValue::Time),
+            SortKey::Size => fs::metadata(path).ok().map(|m| SortValue::Number(m.len())),
+            SortKey::Dimensions => metadata::dimensions(path)
+                .map(|(w, h)| SortValue::Number(w.max(0) as u64 * h.max(0) as u64)),
+            SortKey::ExifDate => metadata::exif_date(path).map(SortValue::Text),
+            SortKey::Name | SortKey::NaturalName | SortKey::Shuffle => None,
+        }
+    }
+
+    /// Reads the values of `paths` in parallel
+    pub fn read_values(self, paths: &[PathBuf]) -> SortValues {
+        if !self.reads_files() {
+            return SortValues::new();
+        }
+        paths
+            .par_iter()
+            .filter_map(|path| Some((path.clone(), self.value(path)?)))
+            .collect()
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match *self {
+            SortKey::Name => "name",
+            SortKey::NaturalName => "natural_name",
+            SortKey::Mtime => "mtime",
+            SortKey::Size => "size",
+            SortKey::Dimensions => "dimensions",
+            SortKey::ExifDate => "exif_date",
+            SortKey::Shuffle => "shuffle",
+        };
+        f.write_str(s)
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SORT_KEYS
+            .iter()
+            .cloned()
+            .find(|key| key.to_string() == s)
+            .ok_or_else(|| {
+                let keys: Vec<String> = SORT_KEYS.iter().map(ToString::to_string).collect();
+                format!(
+                    "Unknown sort key {:?}, expected one of {}",
+                    s,
+                    keys.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct SortOpts {
+    pub by: SortKey,
+    pub reverse: bool,
+    /// Whether natural name comparison ignores case
+    pub ignore_case: bool,
+}
+
+impl Default for SortOpts {
+    fn default() -> Self {
+        SortOpts {
+            by: SortKey::NaturalName,
+            reverse: false,
+            ignore_case: false,
+        }
+    }
+}
+
+impl fmt::Display for SortOpts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.by)?;
+        if self.reverse {
+            f.write_str(" reversed")?;
+        }
+        Ok(())
+    }
+}
+
+impl SortOpts {
+    /// How names are compared, also breaks ties between files with equal keys
+    pub fn name_order(&self) -> NameOrder {
+        match self.by {
+            SortKey::Name => NameOrder::Plain,
+            _ if self.ignore_case => NameOrder::NaturalIgnoreCase,
+            _ => NameOrder::Natural,
+        }
+    }
+
+    pub fn sort(&self, paths: &mut [PathBuf]) {
+        self.sort_with_names(paths, |path| Cow::Borrowed(path))
+    }
+
+    /// Like `sort` but names are compared by what `name_of` returns, e.g. where a file extracted
+    /// to a tempdir is in its archive. Sort values are always read from the actual path.
+    pub fn sort_with_names<F>(&self, paths: &mut [PathBuf], name_of: F)
+    where
+        F: for<'a> Fn(&'a Path) -> Cow<'a, Path> + Sync,
+    {
+        let values = self.by.read_values(paths);
+        self.sort_by_values(paths, &values, name_of);
+    }
+
+    /// Like `sort_with_names` with values that were read before. Files without a value go last,
+    /// also when reversed.
+    pub fn sort_by_values<F>(&self, paths: &mut [PathBuf], values: &SortValues, name_of: F)
+    where
+        F: for<'a> Fn(&'a Path) -> Cow<'a, Path> + Sync,
+    {
+        if self.by == SortKey::Shuffle {
+            paths.shuffle(&mut thread_rng());
+            return;
+        }
+
+        // look every value up once instead of for every comparison
+        let mut keyed: Vec<(Option<&SortValue>, PathBuf)> = paths
+            .iter()
+            .map(|path| (values.get(path), path.clone()))
+            .collect();
+        keyed.par_sort_by(|(a_value, a), (b_value, b)| {
+            self.cmp_with_values((*a_value, a), (*b_value, b), &name_of)
+        });
+        for (slot, (_, path)) in paths.iter_mut().zip(keyed) {
+            *slot = path;
+        }
+    }
+
+    fn cmp_with_values<F>(
+        &self,
+        (a_value, a): (Option<&SortValue>, &Path),
+        (b_value, b): (Option<&SortValue>, &Path),
+        name_of: F,
+    ) -> Ordering
+    where
+        F: for<'a> Fn(&'a Path) -> Cow<'a, Path>,
+    {
+        // the same archive opened twice has the same names in different tempdirs
+        let cmp_names = || {
+            self.name_order()
+                .cmp_paths(&name_of(a), &name_of(b))
+                .then_with(|| a.cmp(b))
+        };
+        let ord = a_value.cmp(&b_value).then_with(cmp_names);
+        let ord = if self.reverse { ord.reverse() } else { ord };
+        a_value.is_none().cmp(&b_value.is_none()).then(ord)
+    }
+
+    /// Sorts the files in each of `dirs` among themselves. They stay in blocks in the order of
+    /// `dirs`, like they were given on the command line, and other files stay where they are. With
+    /// no `dirs` everything gets sorted.
+    pub fn sort_per_dir<F>(
+        &self,
+        paths: &mut [PathBuf],
+        dirs: &[PathBuf],
+        values: &SortValues,
+        name_of: F,
+    ) where
+        F: for<'a> Fn(&'a Path) -> Cow<'a, Path> + Sync,
+    {
+        if dirs.is_empty() {
+            self.sort_by_values(paths, values, name_of);
+            return;
+        }
+        let mut start = 0;
+        while start < paths.len() {
+            let dir = dir_of(dirs, &name_of(&paths[start]));
+            let block = block_at(paths, start, dir, dirs, &name_of);
+            if dir.is_some() {
+                self.sort_by_values(&mut paths[block.clone()], values, &name_of);
+            }
+            start = block.end;
+        }
+    }
+
+    /// Inserts `path` where it belongs among the files of its directory in `dirs`, which have to
+    /// be sorted like `sort_per_dir` does. Returns where it went.
+    pub fn insert_per_dir<F>(
+        &self,
+        paths: &mut Vec<PathBuf>,
+        path: PathBuf,
+        dirs: &[PathBuf],
+        values: &SortValues,
+        name_of: F,
+    ) -> usize
+    where
+        F: for<'a> Fn(&'a Path) -> Cow<'a, Path>,
+    {
+        let dir = dir_of(dirs, &name_of(&path));
+        let block = match dir {
+            _ if dirs.is_empty() => 0..paths.len(),
+            None => paths.len()..paths.len(),
+            Some(i) => match paths.iter().position(|p| dir_of(dirs, &name_of(p)) == dir) {
+                Some(start) => block_at(paths, start, dir, dirs, &name_of),
+                // before the files of the directories given after it
+                None => {
+                    let at = paths
+                        .iter()
+                        .position(|p| matches!(dir_of(dirs, &name_of(p)), Some(j) if j > i))
+                        .unwrap_or(paths.len());
+                    at..at
+                }
+            },
+        };
+
+        let at = if self.by == SortKey::Shuffle {
+            block.end
+        } else {
+            let value = values.get(&path);
+            block.start
+                + paths[block].partition_point(|p| {
+                    self.cmp_with_values((values.get(p), p), (value, &path), &name_of)
+                        != Ordering::Greater
+                })
+        };
+        paths.insert(at, path);
+        at
+    }
+}
+
+/// Index of the directory in `dirs` that `path` is in, the innermost one if they are nested
+fn dir_of(dirs: &[PathBuf], path: &Path) -> Option<usize> {
+    dirs.iter()
+        .enumerate()
+        .filter(|(_, dir)| path.starts_with(dir))
+        .max_by_key(|(_, dir)| dir.components().count())
+        .map(|(i, _)| i)
+}
+
+/// The files from `start` on that are in `dir`
+fn block_at<F>(
+    paths: &[PathBuf],
+    start: usize,
+    dir: Option<usize>,
+    dirs: &[PathBuf],
+    name_of: F,
+) -> Range<usize>
+where
+    F: for<'a> Fn(&'a Path) -> Cow<'a, Path>,
+{
+    let len = paths[start..]
+        .iter()
+        .take_while(|p| dir_of(dirs, &name_of(p)) == dir)
+        .count();
+    start..start + len
+}
+
+fn take_digits(it: &mut Peekable<Chars<'_>>) -> String {
+    let mut ret = String::new();
+    while let Some(&ch) = it.peek() {
+        if !ch.is_ascii_digit() {
+            break;
+        }
+        ret.push(ch);
+        it.next();
+    }
+    ret
+}
+
+/// Compares digit runs by value without parsing them so arbitrarily long numbers work
+fn cmp_digits(a: &str, b: &str) -> Ordering {
+    let a_trimmed = a.trim_start_matches('0');
+    let b_trimmed = b.trim_start_matches('0');
+    a_trimmed
+        .len()
+        .cmp(&b_trimmed.len())
+        .then_with(|| a_trimmed.cmp(b_trimmed))
+        .then_with(|| a.len().cmp(&b.len()))
+}
+
+/// Compares strings like a human would: runs of digits are compared as numbers
+pub fn natural_cmp(a: &str, b: &str, ignore_case: bool) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        let ord = match (a.peek().cloned(), b.peek().cloned()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                cmp_digits(&take_digits(&mut a), &take_digits(&mut b))
+            }
+            (Some(x), Some(y)) => {
+                a.next();
+                b.next();
+                if ignore_case {
+                    x.to_lowercase().cmp(y.to_lowercase())
+                } else {
+                    x.cmp(&y)
+                }
+            }
+        };
+
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+#[test]
+fn natural_order() {
+    let mut names = vec!["page10", "page2", "Page3", "page1", "page02", "cover"];
+    names.sort_by(|a, b| natural_cmp(a, b, false));
+    assert_eq!(
+        names,
+        vec!["Page3", "cover", "page1", "page2", "page02", "page10"]
+    );
+    names.sort_by(|a, b| natural_cmp(a, b, true));
+    assert_eq!(
+        names,
+        vec!["cover", "page1", "page2", "page02", "Page3", "page10"]
+    );
+    assert_eq!(
+        natural_cmp("99999999999999999999999", "100000000000000000000000", false),
+        Ordering::Less
+    );
+}
+
+#[test]
+fn natural_paths() {
+    let mut paths: Vec<PathBuf> = vec!["ch10/1.png", "ch2/10.png", "ch2/9.png", "ch2.png"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    NameOrder::Natural.sort(&mut paths);
+    let expected: Vec<PathBuf> = vec!["ch2/9.png", "ch2/10.png", "ch2.png", "ch10/1.png"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(paths, expected);
+
+    // names that only differ in case don't depend on the order they were found in
+    let mut upper_first = vec![PathBuf::from("A.png"), PathBuf::from("a.png")];
+    let mut lower_first = vec![PathBuf::from("a.png"), PathBuf::from("A.png")];
+    NameOrder::NaturalIgnoreCase.sort(&mut upper_first);
+    NameOrder::NaturalIgnoreCase.sort(&mut lower_first);
+    assert_eq!(upper_first, lower_first);
+}
+
+#[test]
+fn sort_key_roundtrip() {
+    let mut key = SortKey::Name;
+    for _ in 0..SORT_KEYS.len() {
+        assert_eq!(key.to_string().parse::<SortKey>(), Ok(key));
+        key = key.next();
+    }
+    assert_eq!(key, SortKey::Name);
+    assert!("date".parse::<SortKey>().is_err());
+}
+
+#[test]
+fn sort_missing_values_last() {
+    let mut paths: Vec<PathBuf> = vec!["none.png", "small.png", "big.png"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    let mut values = SortValues::new();
+    values.insert(PathBuf::from("small.png"), SortValue::Number(1));
+    values.insert(PathBuf::from("big.png"), SortValue::Number(2));
+    let mut opts = SortOpts {
+        by: SortKey::Size,
+        ..SortOpts::default()
+    };
+    opts.sort_by_values(&mut paths, &values, |path| Cow::Borrowed(path));
+    assert_eq!(paths[0], PathBuf::from("small.png"));
+    assert_eq!(paths[2], PathBuf::from("none.png"));
+    opts.reverse = true;
+    opts.sort_by_values(&mut paths, &values, |path| Cow::Borrowed(path));
+    assert_eq!(paths[0], PathBuf::from("big.png"));
+    assert_eq!(paths[2], PathBuf::from("none.png"));
+}
+
+#[test]
+fn insert_keeps_dir_order() {
+    let paths = |names: &[&str]| -> Vec<PathBuf> { names.iter().map(PathBuf::from).collect() };
+    // `iv b a`
+    let dirs = paths(&["b", "a"]);
+    let mut list = paths(&["b/1.png", "b/3.png", "a/1.png", "a/3.png"]);
+    let opts = SortOpts::default();
+    let values = SortValues::new();
+    let at = opts.insert_per_dir(
+        &mut list,
+        PathBuf::from("a/2.png"),
+        &dirs,
+        &values,
+        |path| Cow::Borrowed(path),
+    );
+    assert_eq!(at, 3);
+    opts.insert_per_dir(
+        &mut list,
+        PathBuf::from("b/2.png"),
+        &dirs,
+        &values,
+        |path| Cow::Borrowed(path),
+    );
+    assert_eq!(
+        list,
+        paths(&["b/1.png", "b/2.png", "b/3.png", "a/1.png", "a/2.png", "a/3.png"])
+    );
+
+    // a reversed sort keeps the blocks in place
+    let reversed = SortOpts {
+        reverse: true,
+        ..opts
+    };
+    reversed.sort_per_dir(&mut list, &dirs, &values, |path| Cow::Borrowed(path));
+    assert_eq!(
+        list,
+        paths(&["b/3.png", "b/2.png", "b/1.png", "a/3.png", "a/2.png", "a/1.png"])
+    );
+}
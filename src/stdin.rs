@@ -0,0 +1,52 @@
+use std::{
+    ffi::OsString,
+    io::{self, BufRead, BufReader},
+    os::unix::ffi::OsStringExt,
+    path::PathBuf,
+    thread,
+};
+
+/// Upper bound so a fast producer doesn't starve the gui of updates
+const MAX_BATCH: usize = 1024;
+
+/// Reads paths separated by newlines, or NUL bytes if `null` is set, from stdin in a background
+/// thread. Paths are sent in batches as soon as reading would block, so the first image doesn't
+/// wait for a slow producer.
+pub fn read_paths(null: bool, sender: glib::Sender<Vec<PathBuf>>) {
+    thread::spawn(move || {
+        let sep = if null { b'\0' } else { b'\n' };
+        // `StdinLock` doesn't expose its buffer
+        let mut stdin = BufReader::new(io::stdin());
+        let mut batch = Vec::new();
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match stdin.read_until(sep, &mut buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Can't read paths from stdin: {}", e);
+                    break;
+                }
+            }
+            if buf.last() == Some(&sep) {
+                buf.pop();
+            }
+            if !buf.is_empty() {
+                batch.push(PathBuf::from(OsString::from_vec(buf.clone())));
+            }
+
+            // nothing buffered means the next read might block
+            if !batch.is_empty() && (stdin.buffer().is_empty() || batch.len() >= MAX_BATCH) {
+                if sender.send(batch).is_err() {
+                    return;
+                }
+                batch = Vec::new();
+            }
+        }
+
+        if !batch.is_empty() {
+            let _ = sender.send(batch);
+        }
+    });
+}
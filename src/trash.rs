@@ -0,0 +1,112 @@
+use std::{
+    env,
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::config;
+
+/// A file moved into the trash by `trash`
+#[derive(Debug)]
+pub struct TrashedFile {
+    /// Absolute path the file was moved from
+    pub original: PathBuf,
+    pub file: PathBuf,
+    pub info: PathBuf,
+}
+
+fn trash_dir() -> io::Result<PathBuf> {
+    Ok(config::data_dir()?.join("Trash"))
+}
+
+/// `fs::rename` that also works across file systems
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(ref e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        res => res,
+    }
+}
+
+/// Moves `path` into the home trash like the freedesktop trash spec says. Files on other file
+/// systems get copied there, the per device trash directories aren't used.
+pub fn trash(path: &Path) -> io::Result<TrashedFile> {
+    let original = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        env::current_dir()?.join(path)
+    };
+    let name = original
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+
+    let trash = trash_dir()?;
+    let files_dir = trash.join("files");
+    let info_dir = trash.join("info");
+    fs::create_dir_all(&files_dir)?;
+    fs::create_dir_all(&info_dir)?;
+
+    // the spec wants the path percent encoded like in an uri
+    let uri = glib::filename_to_uri(&original, None)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let encoded = uri.trim_start_matches("file://");
+    let date = glib::DateTime::new_now_local()
+        .format("%Y-%m-%dT%H:%M:%S")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Can't format deletion date"))?;
+
+    let mut suffix = 0;
+    loop {
+        let mut trash_name = name.to_owned();
+        if suffix > 0 {
+            trash_name.push(format!(".{}", suffix));
+        }
+        suffix += 1;
+
+        let file = files_dir.join(&trash_name);
+        let mut info_name = OsString::from(&trash_name);
+        info_name.push(".trashinfo");
+        let info = info_dir.join(info_name);
+        if file.symlink_metadata().is_ok() {
+            continue;
+        }
+
+        // creating the info file first reserves the name
+        let mut fh = match OpenOptions::new().write(true).create_new(true).open(&info) {
+            Ok(fh) => fh,
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        let moved = write!(
+            fh,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encoded, date
+        )
+        .and_then(|_| move_file(&original, &file));
+        if let Err(e) = moved {
+            let _ = fs::remove_file(&info);
+            return Err(e);
+        }
+
+        return Ok(TrashedFile {
+            original,
+            file,
+            info,
+        });
+    }
+}
+
+/// Moves a trashed file back, fails if something else is at its old place by now
+pub fn restore(trashed: &TrashedFile) -> io::Result<()> {
+    if trashed.original.symlink_metadata().is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "Something else is at its old place",
+        ));
+    }
+    move_file(&trashed.file, &trashed.original)?;
+    fs::remove_file(&trashed.info)
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use directories::BaseDirs;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{config, keys::KeyPress};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriageMode {
+    Move,
+    Copy,
+}
+
+/// What happens if the destination already has a file with that name
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Collision {
+    Skip,
+    /// Append a counter, `a.png` becomes `a_1.png`
+    Rename,
+    /// The old file goes into the trash so it can be restored
+    Overwrite,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Triage {
+    pub mode: TriageMode,
+    pub on_collision: Collision,
+    /// Keys and the directories they send the current image to, `~` is the home directory
+    pub destinations: HashMap<KeyPress, PathBuf>,
+}
+
+impl Default for Triage {
+    fn default() -> Self {
+        Triage {
+            mode: TriageMode::Move,
+            on_collision: Collision::Rename,
+            destinations: HashMap::new(),
+        }
+    }
+}
+
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), BaseDirs::new()) {
+        (Ok(rest), Some(dirs)) => dirs.home_dir().join(rest),
+        _ => path.to_owned(),
+    }
+}
+
+/// Where a file called `name` should go in `dir`, `None` if it should be skipped
+pub fn target_path(dir: &Path, name: &Path, on_collision: Collision) -> Option<PathBuf> {
+    let target = dir.join(name);
+    if target.symlink_metadata().is_err() {
+        return Some(target);
+    }
+
+    match on_collision {
+        Collision::Skip => None,
+        Collision::Overwrite => Some(target),
+        Collision::Rename => {
+            let stem = name.file_stem().unwrap_or(name.as_os_str());
+            (1..)
+                .map(|i| {
+                    let mut new_name = OsString::from(stem);
+                    new_name.push(format!("_{}", i));
+                    if let Some(ext) = name.extension() {
+                        new_name.push(".");
+                        new_name.push(ext);
+                    }
+                    dir.join(new_name)
+                })
+                .find(|path| path.symlink_metadata().is_err())
+        }
+    }
+}
+
+/// Appends `mode`, `from` and `to` as a tab separated line to the journal in the data directory
+pub fn journal(mode: TriageMode, from: &Path, to: &Path) -> io::Result<()> {
+    let dir = config::data_dir()?.join("iv");
+    fs::create_dir_all(&dir)?;
+    let mut fh = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(dir.join("triage.journal"))?;
+    let mode = match mode {
+        TriageMode::Move => "move",
+        TriageMode::Copy => "copy",
+    };
+    writeln!(fh, "{}\t{}\t{}", mode, from.display(), to.display())
+}
+
+#[test]
+fn triage_rename_collision() {
+    let dir = tempfile::TempDir::new().unwrap();
+    fs::write(dir.path().join("a.png"), b"").unwrap();
+    fs::write(dir.path().join("a_1.png"), b"").unwrap();
+    let name = Path::new("a.png");
+    assert_eq!(
+        target_path(dir.path(), name, Collision::Rename),
+        Some(dir.path().join("a_2.png"))
+    );
+    assert_eq!(target_path(dir.path(), name, Collision::Skip), None);
+    assert_eq!(
+        target_path(dir.path(), Path::new("b.png"), Collision::Skip),
+        Some(dir.path().join("b.png"))
+    );
+}
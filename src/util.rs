@@ -34,4 +34,6 @@ pub fn mime_type_buf(buf: &[u8]) -> Result<Mime, failure::Error> {
 
 lazy_static! {
     pub static ref APPLICATION_ZIP: Mime = "application/zip".parse().unwrap();
+    pub static ref APPLICATION_RAR: Mime = "application/x-rar".parse().unwrap();
+    pub static ref APPLICATION_VND_RAR: Mime = "application/vnd.rar".parse().unwrap();
 }
@@ -1,39 +1,214 @@
+mod clipboard;
+mod crop;
+mod dialog;
 mod load;
+mod rename;
 mod setup;
+mod sort;
+mod trash;
+mod triage;
+mod undo;
 
-use self::load::load_file;
+use self::{
+    crop::Crop,
+    load::{load_file, LoadOpts},
+    rename::RenameMode,
+    sort::ReadValues,
+    undo::Undo,
+};
 
-use std::{cell::RefCell, path::PathBuf, rc::Rc};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
 
-use failure;
-use gdk_pixbuf::{InterpType, Pixbuf, PixbufAnimationExt, PixbufRotation};
+use failure::{self, format_err};
+use gdk_pixbuf::{InterpType, Pixbuf, PixbufAnimationExt};
 use gtk::{self, prelude::*};
 use tempfile::TempDir;
 
 use crate::{
-    bottom_bar::BottomBar,
-    config::{Config, MaxFileSize, WinGeom},
+    bottom_bar::{BottomBar, EntryName},
+    config::{ArchiveLimits, Config, MaxFileSize, WinGeom},
+    orientation::{self, JpegRotation, Orientation},
     percent::Percent,
     ratio::*,
+    save::{self, ExportOpts},
     scrollable_image::ScrollableImage,
+    sort::{SortOpts, SortValues},
+    triage::Triage,
 };
 
 pub struct Viewer {
     win: gtk::Window,
     img: ScrollableImage,
     bottom: BottomBar,
+    rename_entry: gtk::Entry,
     _layout: gtk::Box,
     image_paths: Vec<PathBuf>,
     index: usize,
     cur_original_pixbuf: Option<Pixbuf>,
     cur_zoom_level: Percent,
+    /// How the current image was rotated and flipped since it was loaded
+    cur_transform: Orientation,
+    /// When the current file was modified before it was loaded, `None` for files in memory
+    cur_mtime: Option<SystemTime>,
+    /// Path of the image that is shown
+    cur_path: Option<PathBuf>,
+    /// How images looked when they were left, to show them the same way when they are revisited
+    views: HashMap<PathBuf, View>,
+    /// Transform for images that weren't shown before, `None` unless sticky transforms are on
+    sticky: Option<Orientation>,
+    /// The EXIF orientation that was applied to the current image when it was loaded
+    cur_base: Orientation,
+    /// `None` unless in crop mode
+    crop: Option<Crop>,
+    crop_ratios: Vec<Ratio>,
+    export: ExportOpts,
     show_status: bool,
-    tempdirs: Vec<TempDir>,
+    archives: Vec<ExtractedArchive>,
     scaling_algo: InterpType,
     initial_geom: WinGeom,
     max_file_size: MaxFileSize,
+    archive_limits: ArchiveLimits,
+    max_pixels: u64,
+    /// Passwords of encrypted archives that worked in this session
+    passwords: HashMap<PathBuf, String>,
+    /// Tried first for every encrypted archive
+    default_password: Option<String>,
+    sort: SortOpts,
+    /// Directories from the command line, their files stay together in the order they were given
+    dirs: Vec<PathBuf>,
+    /// Values of the files for the current sort key, read in the background
+    sort_values: SortValues,
+    /// Whether the values of all files are still being read, the list gets sorted once they are in
+    sorting: bool,
+    sort_sender: glib::Sender<ReadValues>,
+    /// Files that only exist in memory, like an image read from stdin
+    memory_files: HashMap<PathBuf, MemoryFile>,
+    /// How many images were pasted, to give each one a name
+    pastes: usize,
+    /// Jump to every new file, like `tail -f`
+    follow: bool,
+    /// Why the last file was dropped from the list, shown again once the next one is up
+    skipped: Option<String>,
+    marked: HashSet<PathBuf>,
+    undo: Vec<Undo>,
+    triage: Triage,
+    jpeg_rotation: JpegRotation,
+    /// Apply the EXIF orientation when loading images
+    auto_orient: bool,
+    /// What the rename entry is open for
+    renaming: Option<RenameMode>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct View {
+    transform: Orientation,
+    zoom: Percent,
+    scroll: (f64, f64),
+}
+
+struct MemoryFile {
+    /// Shown instead of the path
+    name: String,
+    data: Vec<u8>,
+}
+
+struct ExtractedArchive {
+    dir: TempDir,
+    /// Where the archive is, with archives it is nested in replaced by their paths. Entries are
+    /// sorted by their name under this.
+    source: PathBuf,
+    /// Display name, contains the names of all archives it is nested in
+    name: String,
+    /// 1 if the archive isn't nested in another archive
+    depth: usize,
+    /// Total bytes extracted from the outermost archive and everything nested in it
+    extracted_size: Rc<Cell<u64>>,
+}
+
+/// Writes the image in `data` to `out` in the format `out`'s extension stands for, like it would
+/// be shown. `path` is only used in error messages.
+pub fn convert(
+    path: &Path,
+    data: &[u8],
+    out: &Path,
+    config: &Config,
+) -> Result<(), failure::Error> {
+    let opts = LoadOpts {
+        max_file_size: &config.max_file_size,
+        archive_limits: &config.archive_limits,
+        archive_depth: 1,
+        archive_budget: config.archive_limits.max_total_size.into(),
+        max_pixels: config.max_pixels,
+        auto_orient: config.auto_orient,
+        password: None,
+    };
+    let loaded = load::load_bytes(path, data, &opts)?;
+    match loaded {
+        load::Loaded::Image {
+            img: load::ImageKind::Image(pixbuf),
+            ..
+        } => save::export(&pixbuf, Some(data), out, config.export),
+        _ => Err(format_err!(
+            "Can't convert {:?}, only still images work",
+            path
+        )),
+    }
 }
 
+/// Runs `f` with the viewer, or a bit later if it's busy. Dialogs run their own main loop while
+/// the viewer is borrowed, so timers and channels can fire while it's in use.
+pub fn with_viewer<F>(viewer: &Rc<RefCell<Viewer>>, f: F)
+where
+    F: FnOnce(&mut Viewer) + 'static,
+{
+    if let Ok(mut viewer) = viewer.try_borrow_mut() {
+        f(&mut viewer);
+        return;
+    }
+
+    let viewer = viewer.clone();
+    let mut f = Some(f);
+    gtk::timeout_add(BUSY_RETRY_MS, move || match viewer.try_borrow_mut() {
+        Ok(mut viewer) => {
+            if let Some(f) = f.take() {
+                f(&mut viewer);
+            }
+            Continue(false)
+        }
+        Err(_) => Continue(true),
+    });
+}
+
+/// Extraction directories and sources of `archives`, unlike `ExtractedArchive` this is `Sync`
+fn archive_roots(archives: &[ExtractedArchive]) -> Vec<(&Path, &Path)> {
+    archives
+        .iter()
+        .map(|archive| (archive.dir.path(), archive.source.as_path()))
+        .collect()
+}
+
+/// Maps files extracted from archives back to a path under the archive so they sort next to it
+fn logical_path<'a>(roots: &[(&Path, &Path)], path: &'a Path) -> Cow<'a, Path> {
+    roots
+        .iter()
+        .find_map(|&(dir, source)| {
+            path.strip_prefix(dir)
+                .ok()
+                .map(|entry| Cow::Owned(source.join(entry)))
+        })
+        .unwrap_or(Cow::Borrowed(path))
+}
+
+const BUSY_RETRY_MS: u32 = 100;
+
 #[derive(Debug, Clone, Copy)]
 enum Zoom {
     In,
@@ -41,10 +216,14 @@ enum Zoom {
 }
 
 impl Viewer {
+    /// Starts at `image_paths[start]`
     pub fn new(
         image_paths: Vec<PathBuf>,
+        start: usize,
+        dirs: Vec<PathBuf>,
         show_status: bool,
         config: Config,
+        default_password: Option<String>,
     ) -> Rc<RefCell<Viewer>> {
         let win = gtk::Window::new(gtk::WindowType::Toplevel);
         win.set_title("iv");
@@ -62,36 +241,78 @@ impl Viewer {
 
         let img = ScrollableImage::new(config.scrollbars);
         let bottom = BottomBar::new(&config.bottom_format);
+        let rename_entry = gtk::Entry::new();
         let layout = gtk::Box::new(gtk::Orientation::Vertical, 0);
         layout.pack_start(img.as_widget(), true, true, 0);
         layout.pack_end(bottom.as_widget(), false, false, 0);
+        layout.pack_end(&rename_entry, false, false, 0);
 
         win.add(&layout);
+        let (sort_sender, sort_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
         let ret = Rc::new(RefCell::new(Viewer {
             win,
             img,
             bottom,
+            rename_entry,
             _layout: layout,
             image_paths,
-            index: 0,
+            index: start,
             cur_original_pixbuf: None,
             cur_zoom_level: Percent::default(),
+            cur_transform: Orientation::NORMAL,
+            cur_mtime: None,
+            cur_path: None,
+            views: HashMap::new(),
+            sticky: None,
+            cur_base: Orientation::NORMAL,
+            crop: None,
+            crop_ratios: config.crop_ratios.clone(),
+            export: config.export,
             show_status: !show_status,
-            tempdirs: Vec::new(),
+            archives: Vec::new(),
             scaling_algo: config.scaling_algo,
             initial_geom: config.initial_geom,
             max_file_size: config.max_file_size,
+            archive_limits: config.archive_limits,
+            max_pixels: config.max_pixels,
+            passwords: HashMap::new(),
+            default_password,
+            sort: config.sort,
+            dirs,
+            sort_values: SortValues::new(),
+            sorting: false,
+            sort_sender,
+            memory_files: HashMap::new(),
+            pastes: 0,
+            follow: false,
+            skipped: None,
+            marked: HashSet::new(),
+            undo: Vec::new(),
+            triage: config.triage.clone(),
+            jpeg_rotation: config.jpeg_rotation,
+            auto_orient: config.auto_orient,
+            renaming: None,
         }));
 
         Viewer::setup(config.keymap, &ret);
+        Viewer::setup_sorting(&ret, sort_receiver);
+        {
+            // the list comes sorted, later additions need the values
+            let mut viewer = ret.borrow_mut();
+            let paths = viewer.image_paths.clone();
+            viewer.read_sort_values(paths, true, false);
+        }
+        if config.auto_reload {
+            Viewer::setup_auto_reload(&ret);
+        }
 
         ret
     }
 
     // needed because gtk::main_quit calls exit and tempdirs destructor doesn't run
     fn quit(&mut self) {
-        for dir in self.tempdirs.drain(0..) {
-            dir.close().expect("Can't close tempdir");
+        for archive in self.archives.drain(0..) {
+            archive.dir.close().expect("Can't close tempdir");
         }
 
         gtk::main_quit();
@@ -106,16 +327,130 @@ impl Viewer {
         }
     }
 
+    /// Makes `path` show `data` instead of reading the file
+    pub fn add_memory_file(&mut self, path: PathBuf, name: String, data: Vec<u8>) {
+        self.memory_files.insert(path, MemoryFile { name, data });
+    }
+
+    fn containing_archive<'a>(&self, path: &'a Path) -> Option<(&ExtractedArchive, &'a Path)> {
+        self.archives.iter().find_map(|archive| {
+            path.strip_prefix(archive.dir.path())
+                .ok()
+                .map(|entry| (archive, entry))
+        })
+    }
+
+    /// Returns the display name of the archive `path` was extracted from and `path` relative to
+    /// it, or just the filename if `path` isn't from an archive
+    fn display_name(&self, path: &Path) -> (Option<String>, String) {
+        if let Some(file) = self.memory_files.get(path) {
+            return (None, file.name.clone());
+        }
+        match self.containing_archive(path) {
+            Some((archive, entry)) => (
+                Some(archive.name.clone()),
+                entry.to_string_lossy().into_owned(),
+            ),
+            None => (
+                None,
+                path.file_name()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+        }
+    }
+
+    fn load_opts(&self, archive_depth: usize, archive_budget: u64) -> LoadOpts<'_> {
+        LoadOpts {
+            max_file_size: &self.max_file_size,
+            archive_limits: &self.archive_limits,
+            archive_depth,
+            archive_budget,
+            max_pixels: self.max_pixels,
+            auto_orient: self.auto_orient,
+            password: None,
+        }
+    }
+
     fn show_current(&mut self) -> Result<(), failure::Error> {
-        let ret = load_file(&self.image_paths[self.index], &self.max_file_size).map_err(|e| {
-            eprintln!("{}", e);
-            e
-        })?;
+        self.remember_view();
+        let path = self.image_paths[self.index].clone();
+        let (depth, extracted_size) = match self.containing_archive(&path) {
+            Some((parent, _)) => (parent.depth + 1, parent.extracted_size.clone()),
+            None => (1, Rc::new(Cell::new(0))),
+        };
+        let budget =
+            u64::from(self.archive_limits.max_total_size).saturating_sub(extracted_size.get());
+        let ret = if let Some(file) = self.memory_files.get(&path) {
+            match load::load_bytes(&path, &file.data, &self.load_opts(depth, budget)) {
+                Ok(ret) => ret,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return Err(e.into());
+                }
+            }
+        } else {
+            loop {
+                let password = self
+                    .passwords
+                    .get(&path)
+                    .or(self.default_password.as_ref())
+                    .cloned();
+                let opts = LoadOpts {
+                    password: password.as_deref(),
+                    ..self.load_opts(depth, budget)
+                };
+                let e = match load_file(&path, &opts) {
+                    Ok(ret) => break ret,
+                    Err(e) => e,
+                };
+                eprintln!("{}", e);
+                if !e.needs_password() {
+                    return Err(e.into());
+                }
+
+                self.passwords.remove(&path);
+                let (archive, entry) = self.display_name(&path);
+                let name = EntryName {
+                    archive: archive.as_deref(),
+                    entry: &entry,
+                }
+                .to_string();
+                match dialog::ask_password(&self.win, &name, password.is_some()) {
+                    Some(password) => {
+                        self.passwords.insert(path.clone(), password);
+                    }
+                    None => return Err(e.into()),
+                }
+            }
+        };
         use crate::viewer::load::{ImageKind, Loaded::*};
 
         match ret {
-            Zip { files, tmp_dir } => {
-                self.tempdirs.push(tmp_dir);
+            Archive {
+                mut files,
+                tmp_dir,
+                extracted_size: size,
+            } => {
+                extracted_size.set(extracted_size.get() + size);
+
+                let (archive, entry) = self.display_name(&path);
+                let name = EntryName {
+                    archive: archive.as_deref(),
+                    entry: &entry,
+                }
+                .to_string();
+                let roots = archive_roots(&self.archives);
+                let source = logical_path(&roots, &path).into_owned();
+                self.archives.push(ExtractedArchive {
+                    dir: tmp_dir,
+                    source,
+                    name,
+                    depth,
+                    extracted_size,
+                });
+                self.sort.name_order().sort(&mut files);
                 self.image_paths.reserve(files.len());
                 let rest = self.image_paths.split_off(self.index);
                 self.image_paths.extend(files);
@@ -123,11 +458,25 @@ impl Viewer {
 
                 self.show_current()
             }
-            Image { size, img } => {
-                let filename = self.image_paths[self.index].file_name().unwrap().to_owned();
-
-                let filename = filename.to_string_lossy();
-                self.win.set_title(&format!("iv - {}", &filename));
+            Image {
+                size,
+                img,
+                orientation,
+            } => {
+                let (archive, entry) = self.display_name(&path);
+                let name = EntryName {
+                    archive: archive.as_deref(),
+                    entry: &entry,
+                };
+                self.win.set_title(&format!("iv - {}", name));
+                self.cur_transform = Orientation::NORMAL;
+                self.cur_base = orientation
+                    .filter(|_| self.auto_orient)
+                    .and_then(Orientation::from_exif)
+                    .unwrap_or(Orientation::NORMAL);
+                self.cur_mtime = fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok();
                 let dims = match img {
                     ImageKind::Animated(anim) => {
                         self.img.set_from_animation(&anim);
@@ -145,34 +494,273 @@ impl Viewer {
                 self.scale_to_fit_current();
 
                 self.bottom.set_info(
-                    &filename,
+                    name,
                     dims,
                     size,
                     self.cur_zoom_level,
                     self.index,
                     self.image_paths.len(),
                 );
+                self.bottom.set_marked(self.marked.contains(&path));
+                self.bottom.set_orientation(orientation);
+                self.restore_view(&path);
+                self.show_unsaved();
                 Ok(())
             }
         }
     }
 
+    /// Like `show_current` but keeps files that failed for reasons the user can fix, like a
+    /// missing password. Returns false if the file should be dropped from the list.
+    fn try_show_current(&mut self) -> bool {
+        match self.show_current() {
+            Ok(()) => {
+                if let Some(msg) = self.skipped.take() {
+                    self.bottom.set_message(&msg);
+                }
+                true
+            }
+            Err(e) => match e.downcast_ref::<load::Error>() {
+                Some(load_err) if load_err.needs_password() => {
+                    self.show_error(&e);
+                    true
+                }
+                Some(load_err) if load_err.is_refusal() => {
+                    let msg = format!("Skipped: {}", e);
+                    self.bottom.set_message(&msg);
+                    self.skipped = Some(msg);
+                    false
+                }
+                _ => false,
+            },
+        }
+    }
+
+    fn show_error(&mut self, e: &failure::Error) {
+        self.img.clear();
+        self.cur_original_pixbuf = None;
+        self.win.set_title("iv");
+        self.bottom.set_message(&e.to_string());
+    }
+
+    /// Adds `paths` to the end of the list, showing the first one if nothing is shown yet
+    pub fn append_paths(&mut self, paths: Vec<PathBuf>) {
+        let was_empty = self.image_paths.is_empty();
+        self.image_paths.extend(paths);
+        if was_empty {
+            self.jump_to(0);
+        } else {
+            self.bottom.set_index(self.index, self.image_paths.len());
+        }
+    }
+
+    pub fn file_created(&mut self, path: PathBuf) {
+        if !self.image_paths.contains(&path) {
+            self.new_file(path);
+        }
+    }
+
+    fn new_file(&mut self, path: PathBuf) {
+        self.insert_paths(vec![path.clone()]);
+        if self.follow {
+            if let Some(i) = self.image_paths.iter().position(|p| *p == path) {
+                self.jump_to(i);
+            }
+        }
+    }
+
+    /// Keeps how the shown image looks, for when it gets shown again
+    fn remember_view(&mut self) {
+        let path = match self.cur_path.take() {
+            Some(path) => path,
+            None => return,
+        };
+        if self.cur_original_pixbuf.is_none() {
+            return;
+        }
+        if self.sticky.is_some() {
+            self.sticky = Some(self.cur_transform);
+        }
+        let view = View {
+            transform: self.cur_transform,
+            zoom: self.cur_zoom_level,
+            scroll: self.img.get_scroll(),
+        };
+        self.views.insert(path, view);
+    }
+
+    /// Shows the newly loaded image at `path` like it was shown last time
+    fn restore_view(&mut self, path: &Path) {
+        self.cur_path = Some(path.to_owned());
+        if self.cur_original_pixbuf.is_none() {
+            return;
+        }
+        match self.views.get(path).copied() {
+            Some(view) => {
+                if !view.transform.is_normal() {
+                    self.transform(view.transform);
+                }
+                self.zoom_to(view.zoom);
+                self.img.set_scroll(view.scroll);
+            }
+            None => {
+                if let Some(transform) = self.sticky.filter(|t| !t.is_normal()) {
+                    self.transform(transform);
+                }
+            }
+        }
+    }
+
+    fn toggle_sticky(&mut self) {
+        if self.sticky.is_some() {
+            self.sticky = None;
+            self.bottom.set_message("Sticky transforms off");
+        } else {
+            self.sticky = Some(self.cur_transform);
+            self.bottom
+                .set_message("Sticky transforms on, new images get rotated like this one");
+        }
+    }
+
+    pub fn toggle_follow(&mut self) {
+        self.follow = !self.follow;
+        if self.follow {
+            self.jump_to_newest();
+            self.bottom.set_message("Following new files");
+        } else {
+            self.bottom.set_message("Stopped following new files");
+        }
+    }
+
+    fn jump_to_newest(&mut self) {
+        let newest = self
+            .image_paths
+            .iter()
+            .enumerate()
+            .filter_map(|(i, path)| Some((fs::metadata(path).ok()?.modified().ok()?, i)))
+            .max();
+        if let Some((_, i)) = newest {
+            self.jump_to(i);
+        }
+    }
+
+    /// Reloads `path` if it's shown, files that only now became viewable are added
+    pub fn file_modified(&mut self, path: PathBuf) {
+        match self.image_paths.iter().position(|p| *p == path) {
+            Some(i) => {
+                // its mtime or size changed
+                self.read_sort_values(vec![path], false, false);
+                if i == self.index {
+                    self.reload();
+                }
+            }
+            None => self.new_file(path),
+        }
+    }
+
+    /// Drops `path` from the list, shows the next file if it was the current one
+    pub fn file_removed(&mut self, path: &Path) {
+        if self.image_paths.is_empty() {
+            return;
+        }
+        let i = match self.image_paths.iter().position(|p| p == path) {
+            Some(i) => i,
+            None => return,
+        };
+        self.image_paths.remove(i);
+        self.marked.remove(path);
+        if i < self.index {
+            self.index -= 1;
+        } else if i == self.index {
+            self.show_after_removal();
+            return;
+        }
+        self.bottom.set_index(self.index, self.image_paths.len());
+    }
+
+    /// Shows what moved into the place of the removed current file
+    fn show_after_removal(&mut self) {
+        if self.image_paths.is_empty() {
+            self.index = 0;
+            self.img.clear();
+            self.cur_original_pixbuf = None;
+            self.win.set_title("iv");
+            self.bottom.set_message("No images left");
+        } else {
+            self.jump_to(self.index);
+        }
+    }
+
+    fn toggle_mark(&mut self) {
+        let path = match self.image_paths.get(self.index) {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let marked = !self.marked.remove(&path);
+        if marked {
+            self.marked.insert(path);
+        }
+        self.bottom.set_marked(marked);
+    }
+
+    /// Indices of the marked files, or just the current one if nothing is marked
+    fn marked_or_current(&self) -> Vec<usize> {
+        if self.image_paths.is_empty() {
+            Vec::new()
+        } else if self.marked.is_empty() {
+            vec![self.index]
+        } else {
+            (0..self.image_paths.len())
+                .filter(|&i| self.marked.contains(&self.image_paths[i]))
+                .collect()
+        }
+    }
+
+    /// Whether `path` is a file the user knows about, not something in memory or extracted from an
+    /// archive
+    fn is_real_file(&self, path: &Path) -> bool {
+        !self.memory_files.contains_key(path) && self.containing_archive(path).is_none()
+    }
+
+    /// Removes the files at `indices`, which have to be sorted, from the list
+    fn remove_indices(&mut self, indices: &[usize]) {
+        if indices.is_empty() || self.image_paths.is_empty() {
+            return;
+        }
+        let current_removed = indices.contains(&self.index);
+        for &i in indices.iter().rev() {
+            let path = self.image_paths.remove(i);
+            self.marked.remove(&path);
+        }
+        self.index -= indices.iter().filter(|&&i| i < self.index).count();
+        if current_removed {
+            self.show_after_removal();
+        } else {
+            self.bottom.set_index(self.index, self.image_paths.len());
+        }
+    }
+
+    pub fn set_scanning(&mut self, scanning: bool) {
+        self.bottom.set_scanning(scanning);
+    }
+
     fn next(&mut self) {
         let tmp = self.index + 1;
         if tmp < self.image_paths.len() {
             self.index = tmp;
-            if self.show_current().is_err() {
+            if !self.try_show_current() {
                 self.image_paths.remove(self.index);
                 self.index -= 1;
                 self.next();
             }
         }
+        self.skipped = None;
     }
 
     fn prev(&mut self) {
         if self.index != 0 {
             self.index -= 1;
-            if self.show_current().is_err() {
+            if !self.try_show_current() {
                 self.image_paths.remove(self.index);
                 if self.index != 0 {
                     self.index -= 1;
@@ -180,6 +768,7 @@ impl Viewer {
                 }
             }
         }
+        self.skipped = None;
     }
 
     fn scale_to_fit_current(&mut self) {
@@ -204,6 +793,8 @@ impl Viewer {
 
     fn set_zoom_info(&mut self, percent: Percent) {
         self.cur_zoom_level = percent;
+        // the selection is drawn over the image
+        self.img.canvas().queue_draw();
         // FIXME: USELESS ALLOC
         self.bottom.set_zoom(self.cur_zoom_level);
     }
@@ -231,6 +822,10 @@ impl Viewer {
                 .cur_zoom_level
                 .step_prev(Percent::from(25_u32), Percent::from(25_u32)),
         };
+        self.zoom_to(percent);
+    }
+
+    fn zoom_to(&mut self, percent: Percent) {
         if let Some(ref pixbuf) = self.cur_original_pixbuf {
             let scaled = rescale(percent, pixbuf.get_width(), pixbuf.get_height()).unwrap();
             let new_buf = pixbuf
@@ -274,41 +869,200 @@ impl Viewer {
     fn jump_to_start(&mut self) {
         self.index = 0;
         while !self.image_paths.is_empty() {
-            if self.show_current().is_err() {
+            if !self.try_show_current() {
                 self.image_paths.remove(0);
             } else {
                 break;
             }
         }
+        self.skipped = None;
+    }
+
+    /// Shows the first loadable file at or after `index`, or the last one if there is none
+    fn jump_to(&mut self, index: usize) {
+        self.index = index;
+        while self.index < self.image_paths.len() {
+            if self.try_show_current() {
+                return;
+            }
+            self.image_paths.remove(self.index);
+        }
+        if !self.image_paths.is_empty() {
+            self.jump_to_end();
+        }
+        self.skipped = None;
     }
 
     fn jump_to_end(&mut self) {
-        self.index = self.image_paths.len() - 1;
-        while !self.image_paths.is_empty() {
-            if self.show_current().is_err() {
-                let len = self.image_paths.len() - 1;
-                self.image_paths.remove(len);
-                self.index -= 1;
-            } else {
+        if self.image_paths.is_empty() {
+            return;
+        }
+        while let Some(last) = self.image_paths.len().checked_sub(1) {
+            self.index = last;
+            if self.try_show_current() {
                 break;
             }
+            self.image_paths.remove(last);
         }
+        self.skipped = None;
     }
 
-    fn rotate(&mut self, rot: PixbufRotation) {
+    /// Rotates or flips the current image
+    fn transform(&mut self, transform: Orientation) {
         let new_orig = if let Some(ref pix) = self.cur_original_pixbuf {
-            pix.rotate_simple(rot)
+            transform.apply(pix)
         } else {
             return;
         };
 
         self.cur_original_pixbuf = new_orig;
+        self.cur_transform = self.cur_transform.then(transform);
         self.scale_to_fit_current();
+        self.show_unsaved();
+    }
+
+    /// Undoes all rotations and flips of the current image
+    fn reset_transform(&mut self) {
+        if !self.cur_transform.is_normal() {
+            self.transform(self.cur_transform.inverse());
+        }
+    }
+
+    fn show_unsaved(&mut self) {
+        let transform = self.cur_transform;
+        self.bottom.set_unsaved(
+            Some(transform)
+                .filter(|t| !t.is_normal())
+                .map(|t| t.to_string()),
+        );
+    }
+
+    /// Exports the current image with its rotations and flips to a file the user picks
+    fn save_as(&mut self) {
+        let pixbuf = match self.cur_original_pixbuf {
+            Some(ref pixbuf) => pixbuf.clone(),
+            None => return,
+        };
+        let path = self.image_paths[self.index].clone();
+        let dir = if self.is_real_file(&path) {
+            path.parent().map(Path::to_owned).unwrap_or_default()
+        } else {
+            PathBuf::new()
+        };
+        let (_, entry) = self.display_name(&path);
+        let suggested = dir.join(
+            Path::new(&entry)
+                .with_extension("png")
+                .file_name()
+                .unwrap_or_default(),
+        );
+        let export = match dialog::ask_export(
+            &self.win,
+            &suggested,
+            self.cur_zoom_level,
+            self.export.strip_metadata,
+        ) {
+            Some(export) => export,
+            None => return,
+        };
+
+        let pixbuf = if export.at_zoom {
+            rescale(self.cur_zoom_level, pixbuf.get_width(), pixbuf.get_height())
+                .and_then(|(width, height)| pixbuf.scale_simple(width, height, self.scaling_algo))
+                .unwrap_or(pixbuf)
+        } else {
+            pixbuf
+        };
+        let original = match self.memory_files.get(&path) {
+            Some(file) => Some(file.data.clone()),
+            None => fs::read(&path).ok(),
+        };
+        let opts = ExportOpts {
+            strip_metadata: export.strip_metadata,
+            ..self.export
+        };
+        match save::export(&pixbuf, original.as_deref(), &export.path, opts) {
+            Ok(()) => {
+                let msg = format!("Saved to {}", export.path.display());
+                self.bottom.set_message(&msg);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                self.bottom.set_message(&e.to_string());
+            }
+        }
+    }
+
+    /// Writes the rotations and flips of the current image to its file
+    fn save_transform(&mut self) {
+        let transform = self.cur_transform;
+        if transform.is_normal() {
+            self.bottom.set_message("Nothing to save");
+            return;
+        }
+        let path = match self.image_paths.get(self.index) {
+            Some(path) if self.is_real_file(path) => path.clone(),
+            Some(_) => {
+                self.bottom.set_message("Only files on disk can be saved");
+                return;
+            }
+            None => return,
+        };
+        let pixbuf = match self.cur_original_pixbuf {
+            Some(ref pixbuf) => pixbuf.clone(),
+            None => return,
+        };
+
+        let res = orientation::save(
+            &path,
+            transform,
+            self.auto_orient,
+            self.jpeg_rotation,
+            &pixbuf,
+        );
+        match res {
+            Ok(notice) => {
+                self.cur_transform = Orientation::NORMAL;
+                self.reload();
+                if let Some(notice) = notice {
+                    self.bottom.set_message(&notice);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                self.bottom.set_message(&e.to_string());
+            }
+        }
+    }
+
+    /// Loads the current file again, keeping zoom, scroll position, rotations and flips
+    fn reload(&mut self) {
+        if self.image_paths.is_empty() {
+            return;
+        }
+        if let Err(e) = self.show_current() {
+            self.show_error(&e);
+        }
+    }
+
+    /// Reloads the current file if it changed on disk since it was loaded
+    pub fn reload_if_changed(&mut self) {
+        let changed = match (self.cur_mtime, self.image_paths.get(self.index)) {
+            (Some(loaded), Some(path)) => fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .map(|mtime| mtime != loaded)
+                .unwrap_or(false),
+            _ => false,
+        };
+        if changed {
+            self.reload();
+        }
     }
 
     pub fn show_all(&mut self) {
         self.win.show_all();
+        self.rename_entry.hide();
         self.toggle_status();
-        self.jump_to_start();
+        self.jump_to(self.index);
     }
 }
@@ -0,0 +1,96 @@
+use std::{cell::RefCell, env, fs, path::PathBuf, rc::Rc};
+
+use gdk_pixbuf::Pixbuf;
+
+use crate::viewer::{with_viewer, Viewer};
+
+fn clipboard() -> gtk::Clipboard {
+    gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD)
+}
+
+impl Viewer {
+    /// Copies the current image as it's shown at 100%
+    pub(in crate::viewer) fn copy_image(&mut self) {
+        match self.cur_original_pixbuf {
+            Some(ref pixbuf) => {
+                clipboard().set_image(pixbuf);
+                self.bottom.set_message("Copied image to clipboard");
+            }
+            None => self.bottom.set_message("Nothing to copy"),
+        }
+    }
+
+    fn absolute_path(&self) -> Option<PathBuf> {
+        let path = self.image_paths.get(self.index)?;
+        if !self.is_real_file(path) {
+            return None;
+        }
+        fs::canonicalize(path)
+            .or_else(|_| env::current_dir().map(|dir| dir.join(path)))
+            .ok()
+    }
+
+    /// Copies the absolute path of the current file, or its `file://` URI
+    pub(in crate::viewer) fn copy_path(&mut self, uri: bool) {
+        let path = match self.absolute_path() {
+            Some(path) => path,
+            None => {
+                self.bottom.set_message("Only files on disk have a path");
+                return;
+            }
+        };
+        let text = if uri {
+            match glib::filename_to_uri(&path, None) {
+                Ok(uri) => uri.as_str().to_owned(),
+                Err(e) => {
+                    self.bottom.set_message(&e.to_string());
+                    return;
+                }
+            }
+        } else {
+            path.to_string_lossy().into_owned()
+        };
+        clipboard().set_text(&text);
+        self.bottom.set_message(&format!("Copied {}", text));
+    }
+
+    /// Shows an image from the clipboard after the current one. The clipboard answers
+    /// asynchronously, so this needs the viewer itself.
+    pub(in crate::viewer) fn paste(viewer: &Rc<RefCell<Viewer>>) {
+        let clipboard = clipboard();
+        if !clipboard.wait_is_image_available() {
+            viewer
+                .borrow_mut()
+                .bottom
+                .set_message("No image in clipboard");
+            return;
+        }
+        let viewer = viewer.clone();
+        clipboard.request_image(move |_, pixbuf| {
+            let pixbuf = pixbuf.clone();
+            with_viewer(&viewer, move |viewer| viewer.add_pasted(&pixbuf));
+        });
+    }
+
+    fn add_pasted(&mut self, pixbuf: &Pixbuf) {
+        let data = match pixbuf.save_to_bufferv("png", &[]) {
+            Ok(data) => data,
+            Err(e) => {
+                self.bottom.set_message(&e.to_string());
+                return;
+            }
+        };
+        self.pastes += 1;
+        let name = format!("<clipboard {}>", self.pastes);
+        let path = PathBuf::from(&name);
+        self.add_memory_file(path.clone(), name, data);
+
+        let index = if self.image_paths.is_empty() {
+            0
+        } else {
+            self.index + 1
+        };
+        self.image_paths.insert(index, path);
+        self.jump_to(index);
+    }
+}
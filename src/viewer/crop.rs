@@ -0,0 +1,295 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use failure::format_err;
+use gdk_pixbuf::Pixbuf;
+use gtk::{self, prelude::*};
+
+use crate::{
+    crop::{self, Rect},
+    orientation::Orientation,
+    save,
+    triage::{self, Collision},
+    viewer::Viewer,
+};
+
+/// How close the pointer has to get to an edge of the image to stick to it, in screen pixels
+const SNAP_PX: f64 = 8.;
+
+#[derive(Debug, Default)]
+pub(in crate::viewer) struct Crop {
+    /// The image the selection was made on, and the selection in pixels of that image as it was
+    /// loaded, before it was rotated or flipped
+    selection: Option<(PathBuf, Rect)>,
+    /// Where dragging started, in pixels of the image as it's shown
+    drag_start: Option<(f64, f64)>,
+    /// Index into the configured ratios, `None` for a free selection
+    ratio: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(in crate::viewer) enum Nudge {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Viewer {
+    pub(in crate::viewer) fn toggle_crop(&mut self) {
+        if self.crop.take().is_some() {
+            self.bottom.set_message("Crop mode off");
+        } else if self.cur_original_pixbuf.is_some() {
+            self.crop = Some(Crop::default());
+            self.bottom
+                .set_message("Crop mode, drag to select and move the selection with the arrows");
+        }
+        self.img.canvas().queue_draw();
+    }
+
+    pub(in crate::viewer) fn cycle_crop_ratio(&mut self) {
+        let nratios = self.crop_ratios.len();
+        let ratio = match self.crop {
+            Some(ref mut crop) => {
+                crop.ratio = match crop.ratio {
+                    None if nratios > 0 => Some(0),
+                    Some(i) if i + 1 < nratios => Some(i + 1),
+                    _ => None,
+                };
+                crop.ratio
+            }
+            None => return,
+        };
+        match ratio {
+            Some(i) => {
+                let msg = format!("Crop ratio {}", self.crop_ratios[i]);
+                self.bottom.set_message(&msg);
+            }
+            None => self.bottom.set_message("Free crop ratio"),
+        }
+    }
+
+    /// How the pixels of the shown image got moved since it was decoded
+    fn loaded_transform(&self) -> Orientation {
+        self.cur_base.then(self.cur_transform)
+    }
+
+    /// Size of the shown image at 100%
+    fn shown_dims(&self) -> Option<(i32, i32)> {
+        let pixbuf = self.cur_original_pixbuf.as_ref()?;
+        Some((pixbuf.get_width(), pixbuf.get_height()))
+    }
+
+    /// Size of the image as it was decoded
+    fn loaded_dims(&self) -> Option<(i32, i32)> {
+        let (width, height) = self.shown_dims()?;
+        if self.loaded_transform().swaps_sides() {
+            Some((height, width))
+        } else {
+            Some((width, height))
+        }
+    }
+
+    /// Where the image starts on the canvas and how much it's scaled
+    fn canvas_geometry(&self) -> Option<((f64, f64), f64)> {
+        let (offset, (width, _)) = self.img.image_geometry()?;
+        let (full_width, _) = self.shown_dims()?;
+        Some((offset, f64::from(width) / f64::from(full_width)))
+    }
+
+    fn canvas_to_image(&self, (x, y): (f64, f64)) -> Option<(f64, f64)> {
+        let ((off_x, off_y), scale) = self.canvas_geometry()?;
+        Some(((x - off_x) / scale, (y - off_y) / scale))
+    }
+
+    /// The selection in pixels of the image as it's shown
+    fn shown_selection(&self) -> Option<Rect> {
+        let (ref path, rect) = *self.crop.as_ref()?.selection.as_ref()?;
+        if self.cur_path.as_ref() != Some(path) {
+            return None;
+        }
+        Some(self.loaded_transform().map_rect(rect, self.loaded_dims()?))
+    }
+
+    fn set_shown_selection(&mut self, rect: Rect) {
+        let loaded = match self.shown_dims() {
+            Some(dims) => self.loaded_transform().inverse().map_rect(rect, dims),
+            None => return,
+        };
+        let path = match self.cur_path {
+            Some(ref path) => path.clone(),
+            None => return,
+        };
+        if let Some(ref mut crop) = self.crop {
+            crop.selection = Some((path, loaded));
+        }
+        self.bottom.set_message(&format!("Selected {}", rect));
+        self.img.canvas().queue_draw();
+    }
+
+    /// Starts a selection, returns whether the click was used for that
+    pub(in crate::viewer) fn crop_press(&mut self, pos: (f64, f64)) -> bool {
+        let start = match self.canvas_to_image(pos) {
+            Some(start) if self.crop.is_some() => start,
+            _ => return false,
+        };
+        if let Some(ref mut crop) = self.crop {
+            crop.drag_start = Some(start);
+            crop.selection = None;
+        }
+        self.img.canvas().queue_draw();
+        true
+    }
+
+    pub(in crate::viewer) fn crop_drag(&mut self, pos: (f64, f64)) -> bool {
+        let (start, ratio) = match self.crop {
+            Some(Crop {
+                drag_start: Some(start),
+                ratio,
+                ..
+            }) => (start, ratio.map(|i| self.crop_ratios[i])),
+            _ => return false,
+        };
+        let (pointer, dims, (_, scale)) = match (
+            self.canvas_to_image(pos),
+            self.shown_dims(),
+            self.canvas_geometry(),
+        ) {
+            (Some(pointer), Some(dims), Some(geometry)) => (pointer, dims, geometry),
+            _ => return false,
+        };
+        let rect = crop::drag_rect(start, pointer, dims, ratio, SNAP_PX / scale);
+        self.set_shown_selection(rect);
+        true
+    }
+
+    pub(in crate::viewer) fn crop_release(&mut self) -> bool {
+        match self.crop {
+            Some(ref mut crop) if crop.drag_start.is_some() => {
+                crop.drag_start = None;
+                if matches!(crop.selection, Some((_, ref rect)) if rect.is_empty()) {
+                    crop.selection = None;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Moves the selection by a pixel on screen, returns false if there is no selection
+    pub(in crate::viewer) fn crop_nudge(&mut self, nudge: Nudge) -> bool {
+        let (rect, dims, (_, scale)) = match (
+            self.shown_selection(),
+            self.shown_dims(),
+            self.canvas_geometry(),
+        ) {
+            (Some(rect), Some(dims), Some(geometry)) => (rect, dims, geometry),
+            _ => return false,
+        };
+        let step = (1. / scale).round().max(1.) as i32;
+        let (dx, dy) = match nudge {
+            Nudge::Left => (-step, 0),
+            Nudge::Right => (step, 0),
+            Nudge::Up => (0, -step),
+            Nudge::Down => (0, step),
+        };
+        self.set_shown_selection(rect.nudged(dx, dy, dims));
+        true
+    }
+
+    /// Darkens everything around the selection
+    pub(in crate::viewer) fn draw_selection(&self, cr: &cairo::Context) {
+        let (rect, ((off_x, off_y), scale), (width, height)) = match (
+            self.shown_selection(),
+            self.canvas_geometry(),
+            self.img.image_geometry(),
+        ) {
+            (Some(rect), Some(geometry), Some((_, dims))) => (rect, geometry, dims),
+            _ => return,
+        };
+        let (width, height) = (f64::from(width), f64::from(height));
+        let x = f64::from(rect.x) * scale;
+        let y = f64::from(rect.y) * scale;
+        let w = f64::from(rect.width) * scale;
+        let h = f64::from(rect.height) * scale;
+
+        cr.translate(off_x, off_y);
+        cr.set_source_rgba(0., 0., 0., 0.5);
+        cr.rectangle(0., 0., width, y);
+        cr.rectangle(0., y + h, width, height - y - h);
+        cr.rectangle(0., y, x, h);
+        cr.rectangle(x + w, y, width - x - w, h);
+        cr.fill();
+
+        cr.set_source_rgb(1., 1., 1.);
+        cr.set_line_width(1.);
+        cr.rectangle(x + 0.5, y + 0.5, (w - 1.).max(0.), (h - 1.).max(0.));
+        cr.stroke();
+    }
+
+    /// The selected part of the shown image
+    fn cropped(&self) -> Result<Pixbuf, failure::Error> {
+        let rect = self
+            .shown_selection()
+            .filter(|rect| !rect.is_empty())
+            .ok_or_else(|| format_err!("Nothing selected"))?;
+        self.cur_original_pixbuf
+            .as_ref()
+            .and_then(|pixbuf| pixbuf.new_subpixbuf(rect.x, rect.y, rect.width, rect.height))
+            .ok_or_else(|| format_err!("Can't crop to {}", rect))
+    }
+
+    fn try_save_crop(&self) -> Result<PathBuf, failure::Error> {
+        let cropped = self.cropped()?;
+        let path = &self.image_paths[self.index];
+        if !self.is_real_file(path) {
+            return Err(format_err!(
+                "Only files on disk can be cropped to a new file"
+            ));
+        }
+        let format = save::writable_format(path)?;
+
+        let mut name = OsString::from(path.file_stem().unwrap_or_default());
+        name.push("_crop");
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let target = triage::target_path(dir, Path::new(&name), Collision::Rename)
+            .ok_or_else(|| format_err!("Can't find a free name for the crop"))?;
+        let original = fs::read(path).ok();
+        save::export_as(&cropped, original.as_deref(), &format, &target, self.export)?;
+        Ok(target)
+    }
+
+    /// Saves the selection next to the current file
+    pub(in crate::viewer) fn save_crop(&mut self) {
+        match self.try_save_crop() {
+            Ok(target) => {
+                let msg = format!("Saved crop to {}", target.display());
+                self.bottom.set_message(&msg);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                self.bottom.set_message(&e.to_string());
+            }
+        }
+    }
+
+    pub(in crate::viewer) fn copy_crop(&mut self) {
+        match self.cropped() {
+            Ok(cropped) => {
+                gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_image(&cropped);
+                self.bottom.set_message("Copied crop to clipboard");
+            }
+            Err(e) => self.bottom.set_message(&e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+use gtk::{self, prelude::*};
+
+use crate::percent::Percent;
+
+/// Asks for the password of the archive `name`, returns None if the user cancelled
+pub(in crate::viewer) fn ask_password(
+    parent: &gtk::Window,
+    name: &str,
+    wrong: bool,
+) -> Option<String> {
+    let dialog = gtk::Dialog::new_with_buttons(
+        Some("iv - Password required"),
+        Some(parent),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        &[
+            ("_Cancel", gtk::ResponseType::Cancel),
+            ("_Open", gtk::ResponseType::Accept),
+        ],
+    );
+    dialog.set_default_response(gtk::ResponseType::Accept);
+
+    let text = if wrong {
+        format!("Wrong password for {}, try again:", name)
+    } else {
+        format!("{} is encrypted, enter its password:", name)
+    };
+    let label = gtk::Label::new(Some(text.as_str()));
+    let entry = gtk::Entry::new();
+    entry.set_visibility(false);
+    entry.set_activates_default(true);
+
+    let content = dialog.get_content_area();
+    content.set_spacing(5);
+    content.pack_start(&label, false, false, 0);
+    content.pack_start(&entry, false, false, 0);
+    dialog.show_all();
+
+    let ret = match dialog.run() {
+        gtk::ResponseType::Accept => entry.get_text().map(|text| text.to_string()),
+        _ => None,
+    };
+    dialog.destroy();
+    ret
+}
+
+/// Where and how to export an image
+pub(in crate::viewer) struct Export {
+    pub path: PathBuf,
+    pub at_zoom: bool,
+    pub strip_metadata: bool,
+}
+
+/// Asks where to save the current image, suggesting `suggested`. Returns None if the user
+/// cancelled.
+pub(in crate::viewer) fn ask_export(
+    parent: &gtk::Window,
+    suggested: &Path,
+    zoom: Percent,
+    strip_metadata: bool,
+) -> Option<Export> {
+    let dialog = gtk::FileChooserDialog::with_buttons(
+        Some("iv - Save as"),
+        Some(parent),
+        gtk::FileChooserAction::Save,
+        &[
+            ("_Cancel", gtk::ResponseType::Cancel),
+            ("_Save", gtk::ResponseType::Accept),
+        ],
+    );
+    dialog.set_default_response(gtk::ResponseType::Accept);
+    dialog.set_do_overwrite_confirmation(true);
+    if let Some(dir) = suggested.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        dialog.set_current_folder(dir);
+    }
+    if let Some(name) = suggested.file_name() {
+        dialog.set_current_name(name);
+    }
+
+    let at_zoom_label = format!("Export at the current zoom of {}", zoom);
+    let at_zoom = gtk::CheckButton::new_with_label(&at_zoom_label);
+    let strip = gtk::CheckButton::new_with_label("Strip metadata");
+    strip.set_active(strip_metadata);
+    let options = gtk::Box::new(gtk::Orientation::Vertical, 5);
+    options.pack_start(&at_zoom, false, false, 0);
+    options.pack_start(&strip, false, false, 0);
+    options.show_all();
+    dialog.set_extra_widget(&options);
+
+    let ret = match dialog.run() {
+        gtk::ResponseType::Accept => dialog.get_filename().map(|path| Export {
+            path,
+            at_zoom: at_zoom.get_active(),
+            strip_metadata: strip.get_active(),
+        }),
+        _ => None,
+    };
+    dialog.destroy();
+    ret
+}
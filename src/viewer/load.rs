@@ -1,8 +1,10 @@
 use std::{
+    cell::Cell,
     fmt,
     fs::File,
     io::{self, prelude::*, BufReader},
     path::{Path, PathBuf},
+    rc::Rc,
 };
 
 use failure::Fail;
@@ -11,11 +13,12 @@ use mime;
 use tempfile::TempDir;
 
 use crate::{
-    config::MaxFileSize,
-    extract::{tmp_extract_zip, ZipError},
-    find,
+    archive::{self, ArchiveBackend, ArchiveKind, RarBackend, RarError, ZipBackend, ZipError},
+    config::{ArchiveLimits, MaxFileSize},
     humane_bytes::HumaneBytes,
-    util::{self, mime_type_buf},
+    metadata,
+    orientation::Orientation,
+    util::mime_type_buf,
 };
 
 type Result<T> = ::std::result::Result<T, Error>;
@@ -46,7 +49,75 @@ pub enum Error {
     GdkPixBuf(#[cause] gdk_pixbuf::Error),
 
     #[fail(display = "Failed unzipping {:?}: {}", _0, _1)]
-    Unzip(PathBuf, #[cause] ZipError),
+    Zip(PathBuf, #[cause] ZipError),
+
+    #[fail(display = "Failed unraring {:?}: {}", _0, _1)]
+    Rar(PathBuf, #[cause] RarError),
+
+    #[fail(
+        display = "Can't open {:?}: Archive nested deeper than {} levels",
+        path, max_depth
+    )]
+    ArchiveTooDeep { path: PathBuf, max_depth: usize },
+
+    #[fail(
+        display = "Can't open {:?}: Nested archives would extract to more than {}",
+        path, size
+    )]
+    ArchiveTooBig { path: PathBuf, size: HumaneBytes },
+
+    #[fail(
+        display = "Can't open {:?}: Archive contains more than {} files",
+        path, max
+    )]
+    TooManyEntries { path: PathBuf, max: usize },
+
+    #[fail(
+        display = "Can't open {:?}: Archive would extract to more than {}",
+        path, size
+    )]
+    UncompressedTooBig { path: PathBuf, size: HumaneBytes },
+
+    #[fail(
+        display = "Can't open {:?}: Compression ratio of {:.0} is above {}, looks like a zip bomb",
+        path, ratio, max
+    )]
+    CompressionRatio { path: PathBuf, ratio: f64, max: f64 },
+
+    #[fail(
+        display = "Can't open {:?}: {}x{} image has more than {} pixels",
+        path, width, height, max
+    )]
+    TooManyPixels {
+        path: PathBuf,
+        width: i32,
+        height: i32,
+        max: u64,
+    },
+}
+
+impl Error {
+    /// Whether the file could be opened with a (different) password
+    pub fn needs_password(&self) -> bool {
+        match *self {
+            Error::Zip(_, ref e) => e.is_password_error(),
+            _ => false,
+        }
+    }
+
+    /// Whether the file was refused because it's over one of the size limits
+    pub fn is_refusal(&self) -> bool {
+        matches!(
+            *self,
+            Error::FileTooBig { .. }
+                | Error::ArchiveTooDeep { .. }
+                | Error::ArchiveTooBig { .. }
+                | Error::TooManyEntries { .. }
+                | Error::UncompressedTooBig { .. }
+                | Error::CompressionRatio { .. }
+                | Error::TooManyPixels { .. }
+        )
+    }
 }
 
 fn do_io<P, R, F>(path: P, f: F) -> Result<R>
@@ -57,7 +128,23 @@ where
     f().map_err(|e| Error::Io(path.as_ref().to_owned(), e))
 }
 
-pub fn load_file<P>(path: P, max_file_size: &MaxFileSize) -> Result<Loaded>
+/// Limits and settings for loading a file
+pub struct LoadOpts<'a> {
+    pub max_file_size: &'a MaxFileSize,
+    pub archive_limits: &'a ArchiveLimits,
+    /// The nesting level an archive would have, 1 if it's not inside of another archive
+    pub archive_depth: usize,
+    /// How much an archive may extract to, what's left of `max_total_size` after the archives it
+    /// is nested in
+    pub archive_budget: u64,
+    pub max_pixels: u64,
+    /// Apply the EXIF orientation to decoded images
+    pub auto_orient: bool,
+    /// Password for encrypted archives
+    pub password: Option<&'a str>,
+}
+
+pub fn load_file<P>(path: P, opts: &LoadOpts<'_>) -> Result<Loaded>
 where
     P: AsRef<Path>,
 {
@@ -76,33 +163,28 @@ where
 
     let ctx = LoaderCtx {
         path,
-        fh,
+        fh: Box::new(fh),
         file_size: metadata.len(),
+        max_pixels: opts.max_pixels,
+        auto_orient: opts.auto_orient,
     };
 
     match file_type {
-        FileType::AnimatedImage | FileType::Image => {
-            if metadata.len() > max_file_size.img.into() {
+        FileType::AnimatedImage | FileType::Image => load_image(ctx, file_type, opts.max_file_size),
+        FileType::Archive(kind) => {
+            if metadata.len() > opts.max_file_size.archive.into() {
                 Err(Error::FileTooBig {
                     path: path.to_owned(),
                     type_: file_type,
-                    size: max_file_size.img,
+                    size: opts.max_file_size.archive,
                 })
-            } else if let FileType::AnimatedImage = file_type {
-                handle_gif(ctx)
-            } else {
-                handle_img(ctx)
-            }
-        }
-        FileType::Zip => {
-            if metadata.len() > max_file_size.zip.into() {
-                Err(Error::FileTooBig {
+            } else if opts.archive_depth > opts.archive_limits.max_depth {
+                Err(Error::ArchiveTooDeep {
                     path: path.to_owned(),
-                    type_: file_type,
-                    size: max_file_size.zip,
+                    max_depth: opts.archive_limits.max_depth,
                 })
             } else {
-                handle_zip(&ctx)
+                handle_archive(&ctx, kind, opts)
             }
         }
 
@@ -110,66 +192,207 @@ where
     }
 }
 
+/// Loads an image that is already in memory, like one read from stdin. `path` is only used in
+/// error messages, the archive options are ignored.
+pub fn load_bytes(path: &Path, data: &[u8], opts: &LoadOpts<'_>) -> Result<Loaded> {
+    let file_type = guess_file_type(path, data)?;
+    let ctx = LoaderCtx {
+        path,
+        fh: Box::new(data),
+        file_size: data.len() as u64,
+        max_pixels: opts.max_pixels,
+        auto_orient: opts.auto_orient,
+    };
+
+    match file_type {
+        FileType::AnimatedImage | FileType::Image => load_image(ctx, file_type, opts.max_file_size),
+        // the archive backends need a file
+        FileType::Archive(_) | FileType::Video => Err(Error::Unsupported(file_type)),
+    }
+}
+
+fn load_image(
+    ctx: LoaderCtx<'_>,
+    file_type: FileType,
+    max_file_size: &MaxFileSize,
+) -> Result<Loaded> {
+    if ctx.file_size > max_file_size.img.into() {
+        Err(Error::FileTooBig {
+            path: ctx.path.to_owned(),
+            type_: file_type,
+            size: max_file_size.img,
+        })
+    } else if let FileType::AnimatedImage = file_type {
+        handle_gif(ctx)
+    } else {
+        handle_img(ctx)
+    }
+}
+
 pub enum ImageKind {
     Image(Pixbuf),
     Animated(PixbufAnimation),
 }
 
 pub enum Loaded {
-    Zip {
+    Archive {
         files: Vec<PathBuf>,
         tmp_dir: TempDir,
+        extracted_size: FileSize,
     },
     Image {
         size: FileSize,
         img: ImageKind,
+        /// The EXIF Orientation tag of the file
+        orientation: Option<u16>,
     },
 }
 
 fn handle_gif(mut ctx: LoaderCtx<'_>) -> Result<Loaded> {
-    ctx.load_pixbuf_with(|loader| loader.get_animation().unwrap())
+    ctx.load_pixbuf_with(|loader, _| loader.get_animation().unwrap())
         .map(|img| Loaded::Image {
             size: ctx.file_size,
             img: ImageKind::Animated(img),
+            orientation: None,
         })
 }
 
 fn handle_img(mut ctx: LoaderCtx<'_>) -> Result<Loaded> {
-    ctx.load_pixbuf_with(|loader| loader.get_pixbuf().unwrap())
-        .map(|img| Loaded::Image {
-            size: ctx.file_size,
-            img: ImageKind::Image(img),
-        })
+    let auto_orient = ctx.auto_orient;
+    ctx.load_pixbuf_with(|loader, buf| {
+        let img = loader.get_pixbuf().unwrap();
+        let orientation = metadata::orientation(buf);
+        let img = orientation
+            .and_then(Orientation::from_exif)
+            .filter(|_| auto_orient)
+            .and_then(|transform| transform.apply(&img))
+            .unwrap_or(img);
+        (img, orientation)
+    })
+    .map(|(img, orientation)| Loaded::Image {
+        size: ctx.file_size,
+        img: ImageKind::Image(img),
+        orientation,
+    })
 }
 
-fn handle_zip(ctx: &LoaderCtx<'_>) -> Result<Loaded> {
-    let extracted = tmp_extract_zip(ctx.path).map_err(|e| Error::Unzip(ctx.path.to_owned(), e))?;
-    let files = find::find_files_rec(extracted.path()).collect();
-    Ok(Loaded::Zip {
+fn handle_archive(ctx: &LoaderCtx<'_>, kind: ArchiveKind, opts: &LoadOpts<'_>) -> Result<Loaded> {
+    match kind {
+        ArchiveKind::Zip => extract_archive(
+            &ZipBackend::with_password(opts.password),
+            ctx,
+            opts,
+            Error::Zip,
+        ),
+        ArchiveKind::Rar => extract_archive(&RarBackend, ctx, opts, Error::Rar),
+    }
+}
+
+fn extract_archive<B>(
+    backend: &B,
+    ctx: &LoaderCtx<'_>,
+    opts: &LoadOpts<'_>,
+    wrap_err: fn(PathBuf, B::Error) -> Error,
+) -> Result<Loaded>
+where
+    B: ArchiveBackend,
+{
+    let path = ctx.path;
+    let limits = opts.archive_limits;
+    let entries = backend
+        .list(path)
+        .map_err(|e| wrap_err(path.to_owned(), e))?;
+
+    if entries.len() > limits.max_entries {
+        return Err(Error::TooManyEntries {
+            path: path.to_owned(),
+            max: limits.max_entries,
+        });
+    }
+
+    let uncompressed: u64 = entries.iter().map(|entry| entry.size).sum();
+    if uncompressed > limits.max_uncompressed_size.into() {
+        return Err(Error::UncompressedTooBig {
+            path: path.to_owned(),
+            size: limits.max_uncompressed_size,
+        });
+    }
+
+    if uncompressed > opts.archive_budget {
+        return Err(Error::ArchiveTooBig {
+            path: path.to_owned(),
+            size: limits.max_total_size,
+        });
+    }
+
+    let ratio = uncompressed as f64 / ctx.file_size.max(1) as f64;
+    if ratio > limits.max_compression_ratio {
+        return Err(Error::CompressionRatio {
+            path: path.to_owned(),
+            ratio,
+            max: limits.max_compression_ratio,
+        });
+    }
+
+    // the declared sizes are below the budget but can be lies
+    let (tmp_dir, files) = archive::tmp_extract(backend, path, entries, uncompressed)
+        .map_err(|e| wrap_err(path.to_owned(), e))?;
+    let extracted_size = files
+        .iter()
+        .filter_map(|file| file.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    Ok(Loaded::Archive {
         files,
-        tmp_dir: extracted,
+        tmp_dir,
+        extracted_size,
     })
 }
 
-#[derive(Debug)]
 struct LoaderCtx<'a> {
     path: &'a Path,
-    fh: BufReader<File>,
+    fh: Box<dyn BufRead + 'a>,
     file_size: u64,
+    max_pixels: u64,
+    /// Apply the EXIF orientation to decoded images
+    auto_orient: bool,
 }
 
 impl<'a> LoaderCtx<'a> {
     fn load_pixbuf_with<F, T>(&mut self, f: F) -> Result<T>
     where
-        F: FnOnce(PixbufLoader) -> T,
+        F: FnOnce(PixbufLoader, &[u8]) -> T,
     {
         let mut buf = Vec::with_capacity(self.file_size as usize);
         do_io(self.path, || self.fh.read_to_end(&mut buf))?;
 
+        // the header gets parsed in the first few chunks, don't decode the rest if the image is
+        // too big
+        let dims = Rc::new(Cell::new(None));
         let loader = PixbufLoader::new();
-        loader.write(&buf).map_err(Error::GdkPixBuf)?;
+        let max_pixels = self.max_pixels;
+        let dims_clone = dims.clone();
+        loader.connect_size_prepared(move |loader, width, height| {
+            if width as u64 * height as u64 > max_pixels {
+                dims_clone.set(Some((width, height)));
+                loader.set_size(1, 1);
+            }
+        });
+
+        for chunk in buf.chunks(64 * 1024) {
+            loader.write(chunk).map_err(Error::GdkPixBuf)?;
+            if let Some((width, height)) = dims.get() {
+                let _ = loader.close();
+                return Err(Error::TooManyPixels {
+                    path: self.path.to_owned(),
+                    width,
+                    height,
+                    max: max_pixels,
+                });
+            }
+        }
         loader.close().map_err(Error::GdkPixBuf)?;
-        Ok(f(loader))
+        Ok(f(loader, &buf))
     }
 }
 
@@ -186,8 +409,8 @@ where
         Ok(FileType::Image)
     } else if mime.type_() == mime::VIDEO {
         Ok(FileType::Video)
-    } else if mime == *util::APPLICATION_ZIP {
-        Ok(FileType::Zip)
+    } else if let Some(kind) = ArchiveKind::from_mime(&mime) {
+        Ok(FileType::Archive(kind))
     } else {
         Err(Error::Mime(format!(
             "Can't open file {:?}: Unsupported mime type: {}",
@@ -201,18 +424,17 @@ pub enum FileType {
     Video,
     AnimatedImage,
     Image,
-    Zip,
+    Archive(ArchiveKind),
 }
 
 impl fmt::Display for FileType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use self::FileType::*;
-        let s = match *self {
-            Video => "video",
-            AnimatedImage => "gif",
-            Image => "image",
-            Zip => "zip",
-        };
-        write!(f, "{}", s)
+        match *self {
+            Video => write!(f, "video"),
+            AnimatedImage => write!(f, "gif"),
+            Image => write!(f, "image"),
+            Archive(kind) => write!(f, "{}", kind),
+        }
     }
 }
@@ -0,0 +1,153 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use failure::format_err;
+use gtk::prelude::*;
+
+use crate::{
+    rename,
+    viewer::{undo::Undo, Viewer},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub(in crate::viewer) enum RenameMode {
+    /// Entry contains the new stem
+    Current,
+    /// Entry contains a pattern for `rename::batch_names`
+    Marked,
+}
+
+impl Viewer {
+    pub(in crate::viewer) fn start_rename(&mut self, mode: RenameMode) {
+        let text = match mode {
+            RenameMode::Current => {
+                let path = match self.image_paths.get(self.index) {
+                    Some(path) if self.is_real_file(path) => path,
+                    Some(_) => {
+                        self.bottom.set_message("Only files on disk can be renamed");
+                        return;
+                    }
+                    None => return,
+                };
+                path.file_stem()
+                    .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned())
+            }
+            RenameMode::Marked => {
+                if self.marked.is_empty() {
+                    self.bottom.set_message("No files marked");
+                    return;
+                }
+                "%f_%03i".to_owned()
+            }
+        };
+
+        self.renaming = Some(mode);
+        self.rename_entry.set_text(&text);
+        self.rename_entry.show();
+        self.rename_entry.grab_focus();
+        self.rename_entry.select_region(0, -1);
+    }
+
+    pub(in crate::viewer) fn cancel_rename(&mut self) {
+        self.renaming = None;
+        self.rename_entry.hide();
+    }
+
+    pub(in crate::viewer) fn finish_rename(&mut self) {
+        let mode = match self.renaming {
+            Some(mode) => mode,
+            None => return,
+        };
+        let text = self
+            .rename_entry
+            .get_text()
+            .map_or_else(String::new, |text| text.as_str().to_owned());
+        self.cancel_rename();
+
+        let res = match mode {
+            RenameMode::Current => self.rename_current(&text),
+            RenameMode::Marked => self.rename_marked(&text),
+        };
+        if let Err(e) = res {
+            eprintln!("{}", e);
+            self.bottom.set_message(&e.to_string());
+        }
+    }
+
+    fn rename_current(&mut self, stem: &str) -> Result<(), failure::Error> {
+        if stem.is_empty() || stem.contains('/') {
+            return Err(format_err!("{:?} is not a valid file name", stem));
+        }
+        let old = self.image_paths[self.index].clone();
+        let new = rename::with_stem(&old, stem);
+        if new == old {
+            return Ok(());
+        }
+        if new.symlink_metadata().is_ok() {
+            return Err(format_err!("Refusing to overwrite {:?}", new));
+        }
+
+        self.rename_path(&old, &new)?;
+        self.undo.push(Undo::Rename(vec![(old, new)]));
+        self.reload();
+        Ok(())
+    }
+
+    fn rename_marked(&mut self, pattern: &str) -> Result<(), failure::Error> {
+        let indices = self.marked_or_current();
+        let old: Vec<PathBuf> = indices
+            .iter()
+            .map(|&i| self.image_paths[i].clone())
+            .collect();
+        if let Some(path) = old.iter().find(|path| !self.is_real_file(path)) {
+            return Err(format_err!("Can't rename {:?}, it only exists in iv", path));
+        }
+        let new = rename::batch_names(pattern, &old).map_err(failure::err_msg)?;
+
+        let mut renamed = Vec::new();
+        let mut res = Ok(());
+        for (old, new) in old.into_iter().zip(new) {
+            if old == new {
+                continue;
+            }
+            if let Err(e) = self.rename_path(&old, &new) {
+                res = Err(e);
+                break;
+            }
+            renamed.push((old, new));
+        }
+
+        if !renamed.is_empty() {
+            self.undo.push(Undo::Rename(renamed));
+            self.reload();
+        }
+        res
+    }
+
+    /// Renames `old` on disk and in the file list
+    pub(in crate::viewer) fn rename_path(
+        &mut self,
+        old: &Path,
+        new: &Path,
+    ) -> Result<(), failure::Error> {
+        fs::rename(old, new).map_err(|e| format_err!("Can't rename {:?}: {}", old, e))?;
+        if let Some(path) = self.image_paths.iter_mut().find(|path| *path == old) {
+            *path = new.to_owned();
+        }
+        if self.marked.remove(old) {
+            self.marked.insert(new.to_owned());
+        }
+        if let Some(view) = self.views.remove(old) {
+            self.views.insert(new.to_owned(), view);
+        }
+        if let Some(value) = self.sort_values.remove(old) {
+            self.sort_values.insert(new.to_owned(), value);
+        }
+        if self.cur_path.as_deref() == Some(old) {
+            self.cur_path = Some(new.to_owned());
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,15 @@
 use std::{cell::RefCell, rc::Rc};
 
-use gdk_pixbuf::PixbufRotation;
 use gtk::prelude::*;
 
 use crate::{
     keys::{KeyAction, KeyMap, KeyPress},
+    orientation::Orientation,
     scrollable_image::ScrollT,
-    viewer::Viewer,
+    viewer::{crop::Nudge, rename::RenameMode, sort::ReadValues, with_viewer, Viewer},
 };
+const AUTO_RELOAD_INTERVAL_MS: u32 = 1000;
+
 impl Viewer {
     pub(in crate::viewer) fn setup(keymap: KeyMap, viewer: &Rc<RefCell<Viewer>>) {
         let clone = viewer.clone();
@@ -15,8 +17,20 @@ impl Viewer {
             .borrow_mut()
             .win
             .connect_key_press_event(move |_, key_event| {
+                // password and save dialogs run their own main loop while the viewer is borrowed,
+                // and typing a new name shouldn't trigger actions
+                match clone.try_borrow() {
+                    Ok(viewer) if viewer.renaming.is_none() => {}
+                    _ => return Inhibit(false),
+                }
                 let scroll = |s| clone.borrow().img.scroll(s);
-                let rot = |r| clone.borrow_mut().rotate(r);
+                let transform = |t| clone.borrow_mut().transform(t);
+                // the arrows scroll unless there's a selection to move
+                let nudge = |n, s| {
+                    if !clone.borrow_mut().crop_nudge(n) {
+                        scroll(s)
+                    }
+                };
                 if let Some(action) = keymap.get(&KeyPress(key_event.get_keyval())) {
                     use self::KeyAction::*;
                     match *action {
@@ -40,20 +54,128 @@ impl Viewer {
                         ToggleStatus => clone.borrow_mut().toggle_status(),
                         JumpToStart => clone.borrow_mut().jump_to_start(),
                         JumpToEnd => clone.borrow_mut().jump_to_end(),
-                        RotateClockwise => rot(PixbufRotation::Clockwise),
-                        RotateCounterClockwise => rot(PixbufRotation::Counterclockwise),
-                        RotateUpsideDown => rot(PixbufRotation::Upsidedown),
+                        RotateClockwise => transform(Orientation::rotated(90)),
+                        RotateCounterClockwise => transform(Orientation::rotated(270)),
+                        RotateUpsideDown => transform(Orientation::rotated(180)),
+                        FlipHorizontal => transform(Orientation::FLIP_HORIZONTAL),
+                        FlipVertical => transform(Orientation::FLIP_VERTICAL),
+                        ResetTransform => clone.borrow_mut().reset_transform(),
+                        CycleSort => clone.borrow_mut().cycle_sort(),
+                        ToggleSortReverse => clone.borrow_mut().toggle_sort_reverse(),
+                        ToggleFollow => clone.borrow_mut().toggle_follow(),
+                        ToggleSticky => clone.borrow_mut().toggle_sticky(),
+                        Reload => clone.borrow_mut().reload(),
+                        ToggleMark => clone.borrow_mut().toggle_mark(),
+                        Trash => clone.borrow_mut().trash(),
+                        Undo => clone.borrow_mut().undo(),
+                        Rename => clone.borrow_mut().start_rename(RenameMode::Current),
+                        RenameMarked => clone.borrow_mut().start_rename(RenameMode::Marked),
+                        SaveTransform => clone.borrow_mut().save_transform(),
+                        SaveAs => clone.borrow_mut().save_as(),
+                        ToggleCrop => clone.borrow_mut().toggle_crop(),
+                        CycleCropRatio => clone.borrow_mut().cycle_crop_ratio(),
+                        SaveCrop => clone.borrow_mut().save_crop(),
+                        CopyCrop => clone.borrow_mut().copy_crop(),
+                        CropNudgeLeft => nudge(Nudge::Left, ScrollT::Left),
+                        CropNudgeRight => nudge(Nudge::Right, ScrollT::Right),
+                        CropNudgeUp => nudge(Nudge::Up, ScrollT::Up),
+                        CropNudgeDown => nudge(Nudge::Down, ScrollT::Down),
+                        CopyImage => clone.borrow_mut().copy_image(),
+                        CopyPath => clone.borrow_mut().copy_path(false),
+                        CopyUri => clone.borrow_mut().copy_path(true),
+                        Paste => Viewer::paste(&clone),
                     };
                     Inhibit(true)
+                } else {
+                    Inhibit(clone.borrow_mut().triage(KeyPress(key_event.get_keyval())))
+                }
+            });
+
+        let clone = viewer.clone();
+        viewer
+            .borrow()
+            .rename_entry
+            .connect_activate(move |_| with_viewer(&clone, Viewer::finish_rename));
+
+        let clone = viewer.clone();
+        viewer
+            .borrow()
+            .rename_entry
+            .connect_key_press_event(move |_, key_event| {
+                if key_event.get_keyval() == gdk::enums::key::Escape {
+                    with_viewer(&clone, Viewer::cancel_rename);
+                    Inhibit(true)
                 } else {
                     Inhibit(false)
                 }
             });
 
+        let canvas = viewer.borrow().img.canvas().clone();
+        let clone = viewer.clone();
+        canvas.connect_draw(move |_, cr| {
+            // drawing can happen while a dialog is open
+            if let Ok(viewer) = clone.try_borrow() {
+                viewer.draw_selection(cr);
+            }
+            Inhibit(false)
+        });
+
+        let clone = viewer.clone();
+        canvas.connect_button_press_event(move |_, event| {
+            let used = event.get_button() == 1
+                && match clone.try_borrow_mut() {
+                    Ok(mut viewer) => viewer.crop_press(event.get_position()),
+                    Err(_) => false,
+                };
+            Inhibit(used)
+        });
+
+        let clone = viewer.clone();
+        canvas.connect_motion_notify_event(move |_, event| {
+            let used = match clone.try_borrow_mut() {
+                Ok(mut viewer) => viewer.crop_drag(event.get_position()),
+                Err(_) => false,
+            };
+            Inhibit(used)
+        });
+
+        let clone = viewer.clone();
+        canvas.connect_button_release_event(move |_, event| {
+            let used = event.get_button() == 1
+                && match clone.try_borrow_mut() {
+                    Ok(mut viewer) => viewer.crop_release(),
+                    Err(_) => false,
+                };
+            Inhibit(used)
+        });
+
         let clone = viewer.clone();
         viewer.borrow_mut().win.connect_delete_event(move |_, _| {
-            clone.borrow_mut().quit();
+            // quits once an open dialog is closed
+            with_viewer(&clone, Viewer::quit);
             Inhibit(false)
         });
     }
+
+    pub(in crate::viewer) fn setup_sorting(
+        viewer: &Rc<RefCell<Viewer>>,
+        receiver: glib::Receiver<ReadValues>,
+    ) {
+        let viewer = viewer.clone();
+        receiver.attach(None, move |read| {
+            with_viewer(&viewer, move |viewer| viewer.sort_values_read(read));
+            Continue(true)
+        });
+    }
+
+    pub(in crate::viewer) fn setup_auto_reload(viewer: &Rc<RefCell<Viewer>>) {
+        let clone = viewer.clone();
+        gtk::timeout_add(AUTO_RELOAD_INTERVAL_MS, move || {
+            // skip a tick instead of waiting if a dialog is open
+            if let Ok(mut viewer) = clone.try_borrow_mut() {
+                viewer.reload_if_changed();
+            }
+            Continue(true)
+        });
+    }
 }
@@ -0,0 +1,138 @@
+use std::{path::PathBuf, thread};
+
+use crate::{
+    sort::{SortKey, SortValues},
+    viewer::{archive_roots, logical_path, Viewer},
+};
+
+/// Sort values that were read in the background
+pub(in crate::viewer) struct ReadValues {
+    key: SortKey,
+    values: SortValues,
+    /// Values of all files, not just of newly added ones
+    all: bool,
+    /// Show the sort order once the list is sorted
+    announce: bool,
+}
+
+impl Viewer {
+    pub(in crate::viewer) fn cycle_sort(&mut self) {
+        self.sort.by = self.sort.by.next();
+        self.sort_values.clear();
+        if self.sort.by.reads_files() {
+            self.bottom
+                .set_message(&format!("Sorting by {}", self.sort));
+            let paths = self.image_paths.clone();
+            self.read_sort_values(paths, true, true);
+        } else {
+            self.sorting = false;
+            self.resort();
+        }
+    }
+
+    pub(in crate::viewer) fn toggle_sort_reverse(&mut self) {
+        self.sort.reverse = !self.sort.reverse;
+        if self.sorting {
+            self.bottom
+                .set_message(&format!("Sorting by {}", self.sort));
+        } else {
+            self.resort();
+        }
+    }
+
+    /// Reads the values of `paths` for the current sort key in a background thread. `all` holds
+    /// off sorting until they are in.
+    pub(in crate::viewer) fn read_sort_values(
+        &mut self,
+        paths: Vec<PathBuf>,
+        all: bool,
+        announce: bool,
+    ) {
+        let key = self.sort.by;
+        if !key.reads_files() {
+            return;
+        }
+        if all {
+            self.sorting = true;
+        }
+        let sender = self.sort_sender.clone();
+        thread::spawn(move || {
+            let values = key.read_values(&paths);
+            let _ = sender.send(ReadValues {
+                key,
+                values,
+                all,
+                announce,
+            });
+        });
+    }
+
+    pub(in crate::viewer) fn sort_values_read(&mut self, read: ReadValues) {
+        // the sort key changed since
+        if read.key != self.sort.by {
+            return;
+        }
+        self.sort_values.extend(read.values);
+        if read.all {
+            self.sorting = false;
+        }
+        if read.announce {
+            self.resort();
+        } else {
+            self.sort_keeping_current();
+        }
+    }
+
+    /// Adds `paths` where they belong among the files of their directory, the current image stays
+    /// selected
+    pub fn insert_paths(&mut self, paths: Vec<PathBuf>) {
+        // they go last until their values are read
+        self.read_sort_values(paths.clone(), false, false);
+        let was_empty = self.image_paths.is_empty();
+        let roots = archive_roots(&self.archives);
+        for path in paths {
+            let at = self.sort.insert_per_dir(
+                &mut self.image_paths,
+                path,
+                &self.dirs,
+                &self.sort_values,
+                |path| logical_path(&roots, path),
+            );
+            if at <= self.index && !was_empty {
+                self.index += 1;
+            }
+        }
+        if was_empty {
+            self.jump_to(0);
+        } else {
+            self.bottom.set_index(self.index, self.image_paths.len());
+        }
+    }
+
+    fn resort(&mut self) {
+        self.sort_keeping_current();
+        self.bottom.set_message(&format!("Sorted by {}", self.sort));
+    }
+
+    /// Sorts `image_paths` by the current sort options and the values read so far, the current
+    /// image stays selected
+    fn sort_keeping_current(&mut self) {
+        if self.image_paths.is_empty() || self.sorting {
+            return;
+        }
+        let current = self.image_paths[self.index].clone();
+        let roots = archive_roots(&self.archives);
+        self.sort.sort_per_dir(
+            &mut self.image_paths,
+            &self.dirs,
+            &self.sort_values,
+            |path| logical_path(&roots, path),
+        );
+        self.index = self
+            .image_paths
+            .iter()
+            .position(|path| *path == current)
+            .unwrap_or(0);
+        self.bottom.set_index(self.index, self.image_paths.len());
+    }
+}
@@ -0,0 +1,41 @@
+use crate::{
+    trash,
+    viewer::{
+        undo::{Trashed, Undo},
+        Viewer,
+    },
+};
+
+impl Viewer {
+    pub(in crate::viewer) fn trash(&mut self) {
+        let mut trashed = Vec::new();
+        let mut errors = Vec::new();
+        for i in self.marked_or_current() {
+            let path = &self.image_paths[i];
+            if !self.is_real_file(path) {
+                errors.push(format!("Can't trash {:?}, it only exists in iv", path));
+                continue;
+            }
+            match trash::trash(path) {
+                Ok(file) => trashed.push(Trashed {
+                    path: path.clone(),
+                    index: i,
+                    file,
+                }),
+                Err(e) => errors.push(format!("Can't trash {:?}: {}", path, e)),
+            }
+        }
+
+        if !trashed.is_empty() {
+            let indices: Vec<usize> = trashed.iter().map(|trashed| trashed.index).collect();
+            self.remove_indices(&indices);
+            self.undo.push(Undo::Trash(trashed));
+        }
+        for e in &errors {
+            eprintln!("{}", e);
+        }
+        if let Some(e) = errors.last() {
+            self.bottom.set_message(e);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::{fs, path::Path};
+
+use failure::format_err;
+
+use crate::{
+    keys::KeyPress,
+    trash,
+    triage::{self, TriageMode},
+    viewer::{
+        undo::{Triaged, Undo},
+        Viewer,
+    },
+};
+
+impl Viewer {
+    /// Moves or copies the current file to the directory bound to `key` and shows the next one.
+    /// Returns false if no directory is bound to `key`.
+    pub(in crate::viewer) fn triage(&mut self, key: KeyPress) -> bool {
+        let dir = match self.triage.destinations.get(&key) {
+            Some(dir) => triage::expand_home(dir),
+            None => return false,
+        };
+        if self.image_paths.is_empty() {
+            return true;
+        }
+
+        match self.triage_current(&dir) {
+            Ok(Some(triaged)) => {
+                let moved = triaged.mode == TriageMode::Move;
+                let index = triaged.index;
+                self.undo.push(Undo::Triage(triaged));
+                if moved {
+                    self.remove_indices(&[index]);
+                } else {
+                    self.next();
+                }
+            }
+            Ok(None) => self.bottom.set_message(&format!(
+                "Skipped, {:?} already has a file with that name",
+                dir
+            )),
+            Err(e) => {
+                eprintln!("{}", e);
+                self.bottom.set_message(&e.to_string());
+            }
+        }
+        true
+    }
+
+    fn triage_current(&mut self, dir: &Path) -> Result<Option<Triaged>, failure::Error> {
+        let path = self.image_paths[self.index].clone();
+        if self.memory_files.contains_key(&path) {
+            return Err(format_err!("Can't triage {:?}, it only exists in iv", path));
+        }
+        // extracted files get deleted with their tempdir anyway
+        let mode = if self.containing_archive(&path).is_some() {
+            TriageMode::Copy
+        } else {
+            self.triage.mode
+        };
+
+        let name = match path.file_name() {
+            Some(name) => Path::new(name),
+            None => return Err(format_err!("{:?} has no file name", path)),
+        };
+        fs::create_dir_all(dir).map_err(|e| format_err!("Can't create {:?}: {}", dir, e))?;
+        let target = match triage::target_path(dir, name, self.triage.on_collision) {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        let replaced = if target.symlink_metadata().is_ok() {
+            Some(
+                trash::trash(&target)
+                    .map_err(|e| format_err!("Can't move {:?} to the trash: {}", target, e))?,
+            )
+        } else {
+            None
+        };
+
+        let res = match mode {
+            TriageMode::Move => trash::move_file(&path, &target),
+            TriageMode::Copy => fs::copy(&path, &target).map(|_| ()),
+        };
+        if let Err(e) = res {
+            if let Some(ref replaced) = replaced {
+                let _ = trash::restore(replaced);
+            }
+            return Err(format_err!("Can't put {:?} into {:?}: {}", path, dir, e));
+        }
+        if let Err(e) = triage::journal(mode, &path, &target) {
+            eprintln!("Can't write triage journal: {}", e);
+        }
+
+        Ok(Some(Triaged {
+            path,
+            index: self.index,
+            target,
+            mode,
+            replaced,
+        }))
+    }
+
+    pub(in crate::viewer) fn undo_triage(
+        &mut self,
+        triaged: Triaged,
+    ) -> Result<(), failure::Error> {
+        let Triaged {
+            path,
+            index,
+            target,
+            mode,
+            replaced,
+        } = triaged;
+        match mode {
+            TriageMode::Move => {
+                if path.symlink_metadata().is_ok() {
+                    return Err(format_err!(
+                        "Can't move {:?} back, {:?} exists again",
+                        target,
+                        path
+                    ));
+                }
+                trash::move_file(&target, &path)
+                    .map_err(|e| format_err!("Can't move {:?} back: {}", target, e))?;
+                let index = index.min(self.image_paths.len());
+                self.image_paths.insert(index, path);
+                self.jump_to(index);
+            }
+            TriageMode::Copy => {
+                fs::remove_file(&target)
+                    .map_err(|e| format_err!("Can't remove copy {:?}: {}", target, e))?;
+                if let Some(index) = self.image_paths.iter().position(|p| *p == path) {
+                    self.jump_to(index);
+                }
+            }
+        }
+
+        if let Some(replaced) = replaced {
+            trash::restore(&replaced)
+                .map_err(|e| format_err!("Can't restore overwritten {:?}: {}", target, e))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use crate::{
+    trash::{self, TrashedFile},
+    triage::TriageMode,
+    viewer::Viewer,
+};
+
+/// Something that can be undone
+pub(in crate::viewer) enum Undo {
+    /// Trashed files in the order they were in the list
+    Trash(Vec<Trashed>),
+    Triage(Triaged),
+    /// Old and new paths of renamed files
+    Rename(Vec<(PathBuf, PathBuf)>),
+}
+
+pub(in crate::viewer) struct Trashed {
+    /// The path in the file list
+    pub path: PathBuf,
+    /// Index in the file list before anything got removed
+    pub index: usize,
+    pub file: TrashedFile,
+}
+
+pub(in crate::viewer) struct Triaged {
+    pub path: PathBuf,
+    pub index: usize,
+    pub target: PathBuf,
+    /// `Copy` for archive entries even if files get moved
+    pub mode: TriageMode,
+    /// What was at `target` before it got overwritten
+    pub replaced: Option<TrashedFile>,
+}
+
+impl Viewer {
+    pub(in crate::viewer) fn undo(&mut self) {
+        let op = match self.undo.pop() {
+            Some(op) => op,
+            None => {
+                self.bottom.set_message("Nothing to undo");
+                return;
+            }
+        };
+
+        match op {
+            Undo::Trash(trashed) => {
+                let mut first = None;
+                let mut errors = Vec::new();
+                for Trashed { path, index, file } in trashed {
+                    if let Err(e) = trash::restore(&file) {
+                        errors.push(format!("Can't restore {:?}: {}", file.original, e));
+                        continue;
+                    }
+                    // ascending indices so every file lands where it was
+                    let index = index.min(self.image_paths.len());
+                    self.image_paths.insert(index, path);
+                    first = first.or(Some(index));
+                }
+                if let Some(first) = first {
+                    self.jump_to(first);
+                }
+                for e in &errors {
+                    eprintln!("{}", e);
+                }
+                if let Some(e) = errors.last() {
+                    self.bottom.set_message(e);
+                }
+            }
+            Undo::Rename(renamed) => {
+                let mut errors = Vec::new();
+                for (old, new) in renamed.into_iter().rev() {
+                    if let Err(e) = self.rename_path(&new, &old) {
+                        errors.push(e.to_string());
+                    }
+                }
+                self.reload();
+                for e in &errors {
+                    eprintln!("{}", e);
+                }
+                if let Some(e) = errors.last() {
+                    self.bottom.set_message(e);
+                }
+            }
+            Undo::Triage(triaged) => {
+                if let Err(e) = self.undo_triage(triaged) {
+                    eprintln!("{}", e);
+                    self.bottom.set_message(&e.to_string());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+
+use crate::find::is_viewable;
+
+/// Files that are still empty after this many checks are reported anyway
+const MAX_SETTLE_CHECKS: usize = 20;
+
+pub enum WatchMsg {
+    Created(PathBuf),
+    Removed(PathBuf),
+    Modified(PathBuf),
+}
+
+/// notify reports absolute paths, they get mapped back to how the directory was given so they
+/// match what `find` returned
+struct Root {
+    given: PathBuf,
+    absolute: PathBuf,
+}
+
+fn relative_to_roots(roots: &[Root], path: PathBuf) -> PathBuf {
+    roots
+        .iter()
+        .find_map(|root| {
+            path.strip_prefix(&root.absolute)
+                .ok()
+                .map(|rest| root.given.join(rest))
+        })
+        .unwrap_or(path)
+}
+
+/// Whether `path` is below one of the roots and neither hidden nor deeper than `max_depth`
+/// below it, like `find` would have returned it
+fn is_wanted(roots: &[Root], path: &Path, hidden: bool, max_depth: Option<usize>) -> bool {
+    let rest = match roots
+        .iter()
+        .filter_map(|root| path.strip_prefix(&root.given).ok())
+        .min_by_key(|rest| rest.components().count())
+    {
+        Some(rest) => rest,
+        None => return true,
+    };
+    if matches!(max_depth, Some(depth) if rest.components().count() > depth) {
+        return false;
+    }
+    hidden
+        || !rest
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// A created or modified file that waits until its size stops changing, half written files
+/// can't be loaded
+struct Pending {
+    created: bool,
+    size: Option<u64>,
+    checks: usize,
+    due: Instant,
+}
+
+impl Pending {
+    fn new(path: &Path, created: bool, wait: Duration) -> Self {
+        Pending {
+            created,
+            size: fs::metadata(path).ok().map(|metadata| metadata.len()),
+            checks: 1,
+            due: Instant::now() + wait,
+        }
+    }
+
+    /// Whether the size stayed the same since the last check, `None` if the file is gone
+    fn settled(&mut self, path: &Path, wait: Duration) -> Option<bool> {
+        let size = fs::metadata(path).ok()?.len();
+        let settled = (self.size == Some(size) && size > 0) || self.checks >= MAX_SETTLE_CHECKS;
+        self.size = Some(size);
+        self.checks += 1;
+        self.due = Instant::now() + wait;
+        Some(settled)
+    }
+}
+
+/// Watches `dirs` in a background thread and sends changes to viewable files once their size
+/// stayed the same for `settle_time`. New files are filtered like `find` would, by `hidden` and
+/// `max_depth`.
+pub fn watch_in_background(
+    dirs: Vec<PathBuf>,
+    max_depth: Option<usize>,
+    hidden: bool,
+    settle_time: Duration,
+    sender: glib::Sender<WatchMsg>,
+) -> Result<(), notify::Error> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::watcher(tx, settle_time)?;
+    let mode = if max_depth != Some(1) {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+
+    let cwd = env::current_dir()?;
+    let mut roots = Vec::with_capacity(dirs.len());
+    for dir in dirs {
+        watcher.watch(&dir, mode)?;
+        roots.push(Root {
+            absolute: cwd.join(&dir),
+            given: dir,
+        });
+    }
+
+    thread::spawn(move || {
+        // watching stops when the watcher is dropped
+        let _watcher = watcher;
+        // files settle side by side so a burst of new files only waits once
+        let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+        loop {
+            let event = match pending.values().map(|p| p.due).min() {
+                None => match rx.recv() {
+                    Ok(event) => Some(event),
+                    Err(_) => return,
+                },
+                Some(due) => match rx.recv_timeout(due.saturating_duration_since(Instant::now())) {
+                    Ok(event) => Some(event),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                },
+            };
+
+            let (removed, changed) = match event {
+                Some(DebouncedEvent::Create(path)) => (None, Some((path, true))),
+                Some(DebouncedEvent::Write(path)) => (None, Some((path, false))),
+                Some(DebouncedEvent::Remove(path)) => (Some(path), None),
+                Some(DebouncedEvent::Rename(from, to)) => (Some(from), Some((to, true))),
+                Some(DebouncedEvent::Error(e, path)) => {
+                    eprintln!("Error while watching {:?}: {}", path, e);
+                    (None, None)
+                }
+                _ => (None, None),
+            };
+            if let Some(path) = removed {
+                let path = relative_to_roots(&roots, path);
+                pending.remove(&path);
+                if sender.send(WatchMsg::Removed(path)).is_err() {
+                    return;
+                }
+            }
+            if let Some((path, created)) = changed {
+                let path = relative_to_roots(&roots, path);
+                if is_wanted(&roots, &path, hidden, max_depth) && !pending.contains_key(&path) {
+                    let p = Pending::new(&path, created, settle_time);
+                    pending.insert(path, p);
+                }
+            }
+
+            let now = Instant::now();
+            let mut settled = Vec::new();
+            pending.retain(|path, p| {
+                if p.due > now {
+                    return true;
+                }
+                match p.settled(path, settle_time) {
+                    Some(true) => {
+                        settled.push((path.clone(), p.created));
+                        false
+                    }
+                    Some(false) => true,
+                    None => false,
+                }
+            });
+            for (path, created) in settled {
+                if !is_viewable(&path) {
+                    continue;
+                }
+                let msg = if created {
+                    WatchMsg::Created(path)
+                } else {
+                    WatchMsg::Modified(path)
+                };
+                if sender.send(msg).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[test]
+fn wanted_like_find() {
+    let roots = vec![Root {
+        given: PathBuf::from("pics"),
+        absolute: PathBuf::from("/home/pics"),
+    }];
+    let wanted = |path: &str, hidden, max_depth| {
+        is_wanted(
+            &roots,
+            &relative_to_roots(&roots, path.into()),
+            hidden,
+            max_depth,
+        )
+    };
+    assert!(wanted("/home/pics/a.jpg", false, Some(1)));
+    assert!(!wanted("/home/pics/sub/a.jpg", false, Some(1)));
+    assert!(wanted("/home/pics/sub/a.jpg", false, Some(2)));
+    assert!(wanted("/home/pics/sub/a.jpg", false, None));
+    assert!(!wanted("/home/pics/.cache/a.jpg", false, None));
+    assert!(!wanted("/home/pics/sub/.a.jpg", false, None));
+    assert!(wanted("/home/pics/.cache/a.jpg", true, None));
+}